@@ -13,4 +13,11 @@ fi
 
 cp ./target/release/rpi-mesh-plugin-manager /bin/
 mkdir -p /etc/rpi-mesh-plugin-manager
-cp ./plugins.repo /etc/rpi-mesh-plugin-manager
\ No newline at end of file
+cp ./plugins.repo /etc/rpi-mesh-plugin-manager
+
+# The official repo is not signed yet. Nodes without a trusted key keep using
+# it unsigned instead of skipping it on every command.
+config=/etc/rpi-mesh-plugin-manager/config.conf
+if [[ ! -s /etc/rpi-mesh-plugin-manager/trusted.keys ]] && ! grep -q "^untrusted_repos:" $config 2>/dev/null;then
+    echo "untrusted_repos: official" >> $config
+fi
@@ -0,0 +1,383 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    os::unix::fs::{symlink, PermissionsExt},
+    path::{Component, Path, PathBuf},
+};
+
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+
+use crate::{credentials::Credential, remote};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ArchiveFormat {
+    TarGz,
+    TarZst,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Detects the format from the file name at the end of a location.
+    pub fn from_location(location: &str) -> Option<ArchiveFormat> {
+        let path = match Url::parse(location) {
+            Ok(url) => url.path().to_lowercase(),
+            Err(_) => location.to_lowercase(),
+        };
+
+        if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if path.ends_with(".tar.zst") || path.ends_with(".tzst") {
+            Some(ArchiveFormat::TarZst)
+        } else if path.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Archives are downloaded from http(s) and file urls.
+pub fn is_valid_location(location: &str) -> bool {
+    remote::is_remote(location) || location.starts_with("file://")
+}
+
+/// Downloads an archive into a file without holding it in memory, returning
+/// the sha256 of the archive.
+pub fn download(
+    location: &str,
+    target: &Path,
+    credential: Option<&Credential>,
+) -> Result<String, String> {
+    let mut source: Box<dyn Read> = if remote::is_remote(location) {
+        Box::new(remote::fetch_stream(location, credential)?)
+    } else {
+        let path = Url::parse(location)
+            .ok()
+            .filter(|x| x.scheme() == "file")
+            .and_then(|x| x.to_file_path().ok())
+            .ok_or(format!("Unsupported archive location {}", location))?;
+        Box::new(File::open(path).map_err(|e| e.to_string())?)
+    };
+
+    let mut file = File::create(target).map_err(|e| e.to_string())?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = source.read(&mut buffer).map_err(|e| e.to_string())?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read]).map_err(|e| e.to_string())?;
+    }
+    file.sync_all().map_err(|e| e.to_string())?;
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Extracts an archive into an empty folder. Entries with an absolute path,
+/// a `..` component or a link pointing outside the folder fail the whole
+/// extraction, as do device files and fifos.
+pub fn extract(archive: &Path, format: ArchiveFormat, destination: &Path) -> Result<(), String> {
+    let file = File::open(archive).map_err(|e| e.to_string())?;
+    match format {
+        ArchiveFormat::TarGz => extract_tar(flate2::read::GzDecoder::new(file), destination),
+        ArchiveFormat::TarZst => extract_tar(
+            zstd::Decoder::new(file).map_err(|e| e.to_string())?,
+            destination,
+        ),
+        ArchiveFormat::Zip => extract_zip(file, destination),
+    }
+}
+
+/// Checks that a path from an archive stays inside the extraction folder.
+fn check_path(path: &Path) -> Result<(), String> {
+    let safe = path.components().all(|x| match x {
+        Component::Normal(_) | Component::CurDir => true,
+        Component::ParentDir | Component::RootDir | Component::Prefix(_) => false,
+    });
+    if safe && path.components().next().is_some() {
+        Ok(())
+    } else {
+        Err(format!("Unsafe path {} in archive", path.display()))
+    }
+}
+
+/// Checks that a symlink at `path` pointing to `target` resolves inside the
+/// extraction folder. `..` is only allowed at the start of the target, as
+/// the folders it would step out of could be links themselves.
+fn check_symlink(path: &Path, target: &Path) -> Result<(), String> {
+    let escape = || format!("Link {} in archive points outside of it", path.display());
+    let mut depth = path.components().count() as i64 - 1;
+    let mut descended = false;
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => {
+                depth += 1;
+                descended = true;
+            }
+            Component::CurDir => {}
+            Component::ParentDir if !descended => depth -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(escape())
+            }
+        }
+        if depth < 0 {
+            return Err(escape());
+        }
+    }
+    Ok(())
+}
+
+/// Checks that no folder on the way to an entry is a link extracted before,
+/// so entries cannot be written through links.
+fn check_parents(destination: &Path, path: &Path) -> Result<(), String> {
+    let mut current = destination.to_path_buf();
+    let components: Vec<Component> = path.components().collect();
+    for component in &components[..components.len().saturating_sub(1)] {
+        current.push(component);
+        if fs::symlink_metadata(&current).is_ok_and(|x| x.file_type().is_symlink()) {
+            return Err(format!(
+                "Entry {} in archive is inside a link",
+                path.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn extract_tar<R: Read>(reader: R, destination: &Path) -> Result<(), String> {
+    let mut archive = tar::Archive::new(reader);
+    // Without preserving, modes are masked to 0o777, so setuid, setgid and
+    // sticky bits from the archive are dropped.
+    archive.set_preserve_permissions(false);
+
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        let mut entry = entry.map_err(|e| e.to_string())?;
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_pax_global_extensions() {
+            continue;
+        }
+
+        let path = entry.path().map_err(|e| e.to_string())?.to_path_buf();
+        check_path(&path)?;
+        check_parents(destination, &path)?;
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let target = entry
+                .link_name()
+                .map_err(|e| e.to_string())?
+                .ok_or(format!("Link {} in archive has no target", path.display()))?
+                .to_path_buf();
+            if entry_type.is_symlink() {
+                check_symlink(&path, &target)?;
+            } else {
+                check_path(&target)?;
+                check_parents(destination, &target)?;
+            }
+        } else if !entry_type.is_file() && !entry_type.is_dir() {
+            return Err(format!("Unsupported entry {} in archive", path.display()));
+        }
+
+        if !entry.unpack_in(destination).map_err(|e| e.to_string())? {
+            return Err(format!("Unsafe path {} in archive", path.display()));
+        }
+    }
+    Ok(())
+}
+
+fn extract_zip(file: File, destination: &Path) -> Result<(), String> {
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(|e| e.to_string())?;
+        let path = PathBuf::from(entry.name());
+        check_path(&path)?;
+        check_parents(destination, &path)?;
+        let target_path = destination.join(&path);
+
+        if entry.is_dir() {
+            fs::create_dir_all(&target_path).map_err(|e| e.to_string())?;
+            continue;
+        }
+        if let Some(parent) = target_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+
+        if entry.is_symlink() {
+            let mut target = String::new();
+            entry
+                .read_to_string(&mut target)
+                .map_err(|e| e.to_string())?;
+            check_symlink(&path, Path::new(&target))?;
+            symlink(&target, &target_path).map_err(|e| e.to_string())?;
+        } else {
+            let mut output = File::create(&target_path).map_err(|e| e.to_string())?;
+            io::copy(&mut entry, &mut output).map_err(|e| e.to_string())?;
+            if let Some(mode) = entry.unix_mode() {
+                fs::set_permissions(&target_path, fs::Permissions::from_mode(mode & 0o777))
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an uncompressed tar from (path, type, link target, mode)
+    /// entries, writing the names raw so unsafe paths end up in the archive.
+    fn tar_archive(entries: &[(&str, tar::EntryType, &str, u32)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, entry_type, link, mode) in entries {
+            let data: &[u8] = if entry_type.is_file() { b"data" } else { b"" };
+            let mut header = tar::Header::new_gnu();
+            let gnu = header.as_gnu_mut().unwrap();
+            gnu.name[..path.len()].copy_from_slice(path.as_bytes());
+            gnu.linkname[..link.len()].copy_from_slice(link.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_mode(*mode);
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// Extracts a tar into `extract` inside a fresh folder, so escapes land
+    /// next to it.
+    fn extract_tar_entries(
+        entries: &[(&str, tar::EntryType, &str, u32)],
+    ) -> (tempfile::TempDir, Result<(), String>) {
+        let root = tempfile::tempdir().unwrap();
+        let destination = root.path().join("extract");
+        fs::create_dir(&destination).unwrap();
+        let result = extract_tar(tar_archive(entries).as_slice(), &destination);
+        (root, result)
+    }
+
+    enum ZipEntry<'a> {
+        File(&'a str),
+        Symlink(&'a str, &'a str),
+    }
+
+    fn extract_zip_entries(entries: &[ZipEntry]) -> (tempfile::TempDir, Result<(), String>) {
+        let root = tempfile::tempdir().unwrap();
+        let archive_path = root.path().join("plugin.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&archive_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        for entry in entries {
+            match entry {
+                ZipEntry::File(path) => {
+                    writer.start_file(*path, options).unwrap();
+                    writer.write_all(b"data").unwrap();
+                }
+                ZipEntry::Symlink(path, target) => {
+                    writer.add_symlink(*path, *target, options).unwrap();
+                }
+            }
+        }
+        writer.finish().unwrap();
+
+        let destination = root.path().join("extract");
+        fs::create_dir(&destination).unwrap();
+        let result = extract_zip(File::open(&archive_path).unwrap(), &destination);
+        (root, result)
+    }
+
+    #[test]
+    fn tar_entries_stay_inside_the_folder() {
+        use tar::EntryType::{Link, Regular, Symlink};
+
+        let refused: [&[(&str, tar::EntryType, &str, u32)]; 6] = [
+            &[("../escape", Regular, "", 0o644)],
+            &[("/tmp/escape", Regular, "", 0o644)],
+            &[("link", Symlink, "../escape", 0o777)],
+            &[("link", Symlink, "/etc", 0o777)],
+            &[
+                ("dir", Symlink, "sub", 0o777),
+                ("dir/escape", Regular, "", 0o644),
+            ],
+            &[("hard", Link, "../escape", 0o644)],
+        ];
+        for entries in refused {
+            let (root, result) = extract_tar_entries(entries);
+            assert!(result.is_err(), "{:?} was extracted", entries[0].0);
+            assert!(!root.path().join("escape").exists());
+        }
+
+        let (_root, result) = extract_tar_entries(&[
+            ("sub/file", Regular, "", 0o644),
+            ("dir", Symlink, "sub", 0o777),
+            ("hard", Link, "dir/file", 0o644),
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn tar_links_inside_the_folder_are_extracted() {
+        use tar::EntryType::{Directory, Link, Regular, Symlink};
+
+        let (root, result) = extract_tar_entries(&[
+            ("sub", Directory, "", 0o755),
+            ("sub/file", Regular, "", 0o644),
+            ("sub/link", Symlink, "../top", 0o777),
+            ("hard", Link, "sub/file", 0o644),
+        ]);
+        assert_eq!(result, Ok(()));
+        let destination = root.path().join("extract");
+        assert_eq!(
+            fs::read_link(destination.join("sub/link")).unwrap(),
+            Path::new("../top")
+        );
+        assert_eq!(fs::read(destination.join("hard")).unwrap(), b"data");
+    }
+
+    #[test]
+    fn tar_special_permission_bits_are_dropped() {
+        let (root, result) = extract_tar_entries(&[
+            ("setuid", tar::EntryType::Regular, "", 0o4755),
+            ("sticky", tar::EntryType::Directory, "", 0o1777),
+        ]);
+        assert_eq!(result, Ok(()));
+        let mode = |name: &str| {
+            fs::metadata(root.path().join("extract").join(name))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o7777
+        };
+        assert_eq!(mode("setuid"), 0o755);
+        assert_eq!(mode("sticky"), 0o777);
+    }
+
+    #[test]
+    fn zip_entries_stay_inside_the_folder() {
+        let refused: [&[ZipEntry]; 4] = [
+            &[ZipEntry::File("../escape")],
+            &[ZipEntry::File("/tmp/escape")],
+            &[ZipEntry::Symlink("link", "../escape")],
+            &[
+                ZipEntry::Symlink("dir", "sub"),
+                ZipEntry::File("dir/escape"),
+            ],
+        ];
+        for entries in refused {
+            let (root, result) = extract_zip_entries(entries);
+            assert!(result.is_err());
+            assert!(!root.path().join("escape").exists());
+        }
+
+        let (root, result) = extract_zip_entries(&[
+            ZipEntry::File("sub/file"),
+            ZipEntry::Symlink("sub/link", "../top"),
+        ]);
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            fs::read_link(root.path().join("extract/sub/link")).unwrap(),
+            Path::new("../top")
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+};
+
+/// Hidden sibling of a file, used for temporary and backup copies so they are
+/// never picked up as repos.
+pub fn sibling_path(location: &str, suffix: &str) -> String {
+    let path = Path::new(location);
+    let file_name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    path.with_file_name(format!(".{}.{}", file_name, suffix))
+        .display()
+        .to_string()
+}
+
+/// Replaces files without ever leaving a partially written file behind: the
+/// content is written to a temporary file, synced and renamed over the
+/// original, the previous content is kept as a backup. Files that are only
+/// valid together, like a repo and its signature, are replaced in the given
+/// order. Every temporary file is written before the first rename, and the
+/// files already replaced are restored from their backups when a later
+/// rename fails.
+pub fn replace_files(files: &[(&str, &str)]) -> Result<(), String> {
+    let remove_temporary = |files: &[(&str, &str)]| {
+        for (location, _) in files {
+            let _ = fs::remove_file(sibling_path(location, "tmp"));
+        }
+    };
+
+    for (location, content) in files {
+        if let Err(e) = write_temporary(location, content) {
+            remove_temporary(files);
+            return Err(e.to_string());
+        }
+    }
+
+    let mut replaced: Vec<(&str, bool)> = Vec::new();
+    for (index, (location, _)) in files.iter().enumerate() {
+        match swap_in(location) {
+            Ok(had_previous) => replaced.push((location, had_previous)),
+            Err(e) => {
+                for (location, had_previous) in replaced {
+                    if had_previous {
+                        let _ = fs::copy(sibling_path(location, "bak"), location);
+                    } else {
+                        let _ = fs::remove_file(location);
+                    }
+                }
+                remove_temporary(&files[index..]);
+                return Err(e.to_string());
+            }
+        }
+    }
+
+    for (location, _) in files {
+        if let Some(parent) = Path::new(location).parent() {
+            if let Ok(directory) = File::open(parent) {
+                let _ = directory.sync_all();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_temporary(location: &str, content: &str) -> std::io::Result<()> {
+    let mut file = File::create(sibling_path(location, "tmp"))?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()
+}
+
+/// Renames the temporary file over the original after backing it up,
+/// returns whether there was an original.
+fn swap_in(location: &str) -> std::io::Result<bool> {
+    let had_previous = Path::new(location).is_file();
+    if had_previous {
+        fs::copy(location, sibling_path(location, "bak"))?;
+    }
+    fs::rename(sibling_path(location, "tmp"), location)?;
+    Ok(had_previous)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn replaces_files_in_order_and_keeps_backups() {
+        let root = TempDir::new().unwrap();
+        let repo = root.path().join("a.repo").display().to_string();
+        let signature = sibling_path(&repo, "minisig");
+        fs::write(&repo, "old").unwrap();
+
+        replace_files(&[(&repo, "new"), (&signature, "signature")]).unwrap();
+
+        assert_eq!(fs::read_to_string(&repo).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&signature).unwrap(), "signature");
+        assert_eq!(
+            fs::read_to_string(sibling_path(&repo, "bak")).unwrap(),
+            "old"
+        );
+        assert!(!Path::new(&sibling_path(&repo, "tmp")).exists());
+    }
+
+    #[test]
+    fn failed_rename_restores_the_replaced_files() {
+        let root = TempDir::new().unwrap();
+        let repo = root.path().join("a.repo").display().to_string();
+        let new_repo = root.path().join("b.repo").display().to_string();
+        let blocked = root.path().join("blocked").display().to_string();
+        fs::write(&repo, "old").unwrap();
+        fs::create_dir(&blocked).unwrap();
+        fs::write(root.path().join("blocked/file"), "").unwrap();
+
+        let result = replace_files(&[(&repo, "new"), (&new_repo, "new"), (&blocked, "x")]);
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&repo).unwrap(), "old");
+        assert!(!Path::new(&new_repo).exists());
+        assert!(!Path::new(&sibling_path(&blocked, "tmp")).exists());
+    }
+}
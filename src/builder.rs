@@ -0,0 +1,72 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::process::CommandExt,
+    path::Path,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use chrono::Local;
+use nix::{
+    sys::signal::{killpg, Signal},
+    unistd::Pid,
+};
+use wait_timeout::ChildExt;
+
+/// Runs the build command of a plugin with `sh -c` in its folder. Its output
+/// is appended to the log file, after a line naming the command. The command
+/// is killed with everything it started when it runs longer than the timeout.
+pub fn run(command: &str, folder: &Path, log_path: &Path, timeout: Duration) -> Result<(), String> {
+    if let Some(parent) = log_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .map_err(|e| e.to_string())?;
+    writeln!(
+        log,
+        "[{}] Building in {}: {}",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        folder.display(),
+        command
+    )
+    .map_err(|e| e.to_string())?;
+
+    let stderr = log.try_clone().map_err(|e| e.to_string())?;
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(folder)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone().map_err(|e| e.to_string())?)
+        .stderr(stderr)
+        .process_group(0)
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    let result = match child.wait_timeout(timeout).map_err(|e| e.to_string())? {
+        Some(status) if status.success() => Ok(()),
+        Some(status) => Err(format!("Build command failed with {}", status)),
+        None => {
+            let _ = killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+            let _ = child.wait();
+            Err(format!(
+                "Build command timed out after {} seconds",
+                timeout.as_secs()
+            ))
+        }
+    };
+    let _ = writeln!(
+        log,
+        "{}",
+        match &result {
+            Ok(_) => String::from("Build succeeded"),
+            Err(e) => e.clone(),
+        }
+    );
+
+    result
+}
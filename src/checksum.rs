@@ -0,0 +1,93 @@
+use std::{fs, os::unix::ffi::OsStrExt, path::Path};
+
+use sha2::{Digest, Sha256};
+
+pub fn sha256_hex(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+/// Digest of a directory tree: the sha256 of one `<sha256>  <path>` line per
+/// file, sorted by relative path. Symlinks are not followed, the digest of a
+/// link is the digest of its target path. It is the same as running the
+/// command in the README inside the directory.
+pub fn sha256_dir(path: &Path) -> Result<String, String> {
+    let mut files = Vec::new();
+    collect_files(path, path, &mut files)?;
+    files.sort();
+
+    let mut listing = String::new();
+    for file in files {
+        let file_path = path.join(&file);
+        let is_symlink = fs::symlink_metadata(&file_path)
+            .map_err(|e| e.to_string())?
+            .file_type()
+            .is_symlink();
+        let content = if is_symlink {
+            fs::read_link(&file_path)
+                .map(|x| x.as_os_str().as_bytes().to_vec())
+                .map_err(|e| e.to_string())?
+        } else {
+            fs::read(&file_path).map_err(|e| e.to_string())?
+        };
+        listing.push_str(&format!("{}  {}\n", sha256_hex(&content), file));
+    }
+
+    Ok(sha256_hex(listing.as_bytes()))
+}
+
+fn collect_files(root: &Path, directory: &Path, files: &mut Vec<String>) -> Result<(), String> {
+    for entry in fs::read_dir(directory).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        let file_type = fs::symlink_metadata(&path)
+            .map_err(|e| e.to_string())?
+            .file_type();
+        if file_type.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if file_type.is_file() || file_type.is_symlink() {
+            let relative = path.strip_prefix(root).map_err(|e| e.to_string())?;
+            files.push(relative.display().to_string());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    #[test]
+    fn symlinks_are_hashed_as_their_target() {
+        let root = TempDir::new().unwrap();
+        fs::create_dir(root.path().join("sub")).unwrap();
+        fs::write(root.path().join("sub/file"), "content").unwrap();
+        symlink("/", root.path().join("root")).unwrap();
+        symlink("..", root.path().join("sub/loop")).unwrap();
+
+        let expected = format!(
+            "{}  root\n{}  sub/file\n{}  sub/loop\n",
+            sha256_hex(b"/"),
+            sha256_hex(b"content"),
+            sha256_hex(b"..")
+        );
+        assert_eq!(
+            sha256_dir(root.path()).unwrap(),
+            sha256_hex(expected.as_bytes())
+        );
+    }
+
+    #[test]
+    fn retargeted_symlinks_change_the_digest() {
+        let root = TempDir::new().unwrap();
+        fs::write(root.path().join("file"), "content").unwrap();
+        symlink("file", root.path().join("link")).unwrap();
+        let before = sha256_dir(root.path()).unwrap();
+
+        fs::remove_file(root.path().join("link")).unwrap();
+        symlink("/etc/passwd", root.path().join("link")).unwrap();
+
+        assert_ne!(sha256_dir(root.path()).unwrap(), before);
+    }
+}
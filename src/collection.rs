@@ -0,0 +1,32 @@
+use crate::plugin::Plugin;
+
+/// A collection entry used as a named group of plugins. Members are stored as
+/// repo-qualified names so they resolve to the entries of the collection even
+/// when another repo shadows them.
+#[derive(Debug)]
+pub struct Collection {
+    plugin: Plugin,
+    members: Vec<String>,
+}
+
+impl Collection {
+    pub fn new(plugin: Plugin, members: Vec<String>) -> Collection {
+        Collection { plugin, members }
+    }
+
+    pub fn get_name(&self) -> String {
+        self.plugin.get_name()
+    }
+
+    pub fn get_location(&self) -> String {
+        self.plugin.get_location()
+    }
+
+    pub fn get_repo(&self) -> String {
+        self.plugin.get_repo()
+    }
+
+    pub fn get_members(&self) -> Vec<String> {
+        self.members.clone()
+    }
+}
@@ -0,0 +1,224 @@
+use std::{fs, os::unix::fs::MetadataExt};
+
+use reqwest::Url;
+
+use crate::ini::{Document, Section};
+
+/// Returns the host of a remote location, with the port when it has one.
+/// Scp-like git locations (`user@host:path`) are supported too.
+pub fn host_of(location: &str) -> Option<String> {
+    if let Ok(url) = Url::parse(location) {
+        if let Some(host) = url.host_str() {
+            return Some(match url.port() {
+                Some(port) => format!("{}:{}", host, port),
+                None => host.to_string(),
+            });
+        }
+    }
+
+    let (user_host, _) = location.split_once(':')?;
+    let host = user_host.rsplit('@').next()?;
+    if host.is_empty() || host.contains('/') {
+        None
+    } else {
+        Some(host.to_string())
+    }
+}
+
+/// Credentials for one host. Secrets are never part of any output, so this
+/// type has no `Debug` or `Display`.
+#[derive(Clone)]
+pub struct Credential {
+    username: Option<String>,
+    password: Option<String>,
+    token: Option<String>,
+    ssh_key: Option<String>,
+}
+
+impl Credential {
+    fn from_section(section: &Section) -> Credential {
+        Credential {
+            username: section.get("username"),
+            password: section.get("password"),
+            token: section.get("token"),
+            ssh_key: section.get("ssh_key"),
+        }
+    }
+
+    pub fn get_username(&self) -> Option<String> {
+        self.username.clone()
+    }
+
+    pub fn get_password(&self) -> Option<String> {
+        self.password.clone()
+    }
+
+    pub fn get_token(&self) -> Option<String> {
+        self.token.clone()
+    }
+
+    pub fn get_ssh_key(&self) -> Option<String> {
+        self.ssh_key.clone()
+    }
+
+    /// Username and password for git over http. A token is sent as the
+    /// password, with `git` as username unless one is set.
+    pub fn get_git_login(&self) -> Option<(String, String)> {
+        let username = self.username.clone();
+        match (&self.token, &self.password) {
+            (Some(token), _) => Some((username.unwrap_or(String::from("git")), token.clone())),
+            (None, Some(password)) => Some((username?, password.clone())),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Per host credentials, stored as `[host]` sections with a `token`, a
+/// `username` and `password`, and/or an `ssh_key` path. The file holds
+/// secrets, so it is ignored unless it is owned by and only accessible to
+/// root.
+#[derive(Clone, Default)]
+pub struct Credentials {
+    hosts: Vec<(String, Credential)>,
+}
+
+impl Credentials {
+    /// Loads the credentials, a missing file means no credentials.
+    pub fn load(location: &str) -> Credentials {
+        Credentials::read(location).unwrap_or_else(|e| {
+            println!("Warning! {} Ignoring it!", e);
+            Credentials::default()
+        })
+    }
+
+    /// Reads the credentials file. Errors only name the file, never its
+    /// content.
+    fn read(location: &str) -> Result<Credentials, String> {
+        let metadata = match fs::metadata(location) {
+            Ok(metadata) => metadata,
+            Err(_) => return Ok(Credentials::default()),
+        };
+        if metadata.uid() != 0 || metadata.mode() & 0o077 != 0 {
+            return Err(format!(
+                "Credentials file {} must be owned by root and only accessible to root!",
+                location
+            ));
+        }
+
+        fs::read_to_string(location)
+            .map(|x| Credentials::parse(&x))
+            .map_err(|e| format!("Cannot read credentials file {}! Error: {}", location, e))
+    }
+
+    pub fn parse(content: &str) -> Credentials {
+        Credentials {
+            hosts: Document::parse(content)
+                .get_sections()
+                .iter()
+                .map(|x| (x.get_name(), Credential::from_section(x)))
+                .collect(),
+        }
+    }
+
+    pub fn get_hosts(&self) -> &Vec<(String, Credential)> {
+        &self.hosts
+    }
+
+    /// Finds the credentials for the host of a remote location. Sections are
+    /// named after the host as it is written in the location, with the port
+    /// when the location has one.
+    pub fn get(&self, location: &str) -> Option<&Credential> {
+        let host = host_of(location)?;
+
+        self.hosts
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&host))
+            .map(|(_, credential)| credential)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    const CREDENTIALS: &str = "[github.com]\ntoken=secret-token\n\n\
+        [git.example.org:8443]\nusername=mesh\npassword=secret-password\n\n\
+        [example.org]\nssh_key=/root/.ssh/example\n";
+
+    #[test]
+    fn hosts_are_found_by_location() {
+        assert_eq!(
+            host_of("git@github.com:mesh/web.git").as_deref(),
+            Some("github.com")
+        );
+        assert_eq!(
+            host_of("github.com:mesh/web.git").as_deref(),
+            Some("github.com")
+        );
+        assert_eq!(
+            host_of("ssh://git@example.org/mesh/web.git").as_deref(),
+            Some("example.org")
+        );
+        assert_eq!(
+            host_of("https://user@git.example.org:8443/web.git").as_deref(),
+            Some("git.example.org:8443")
+        );
+        assert_eq!(host_of("/opt/plugins/web"), None);
+        assert_eq!(host_of("./web:1"), None);
+
+        let credentials = Credentials::parse(CREDENTIALS);
+        let token = credentials.get("https://GitHub.com/mesh/web.git").unwrap();
+        assert_eq!(token.get_token().as_deref(), Some("secret-token"));
+        assert_eq!(
+            token.get_git_login(),
+            Some((String::from("git"), String::from("secret-token")))
+        );
+        let login = credentials
+            .get("https://git.example.org:8443/web.git")
+            .unwrap();
+        assert_eq!(
+            login.get_git_login(),
+            Some((String::from("mesh"), String::from("secret-password")))
+        );
+        assert!(credentials.get("https://git.example.org/web.git").is_none());
+        let ssh_key = credentials.get("git@example.org:mesh/web.git").unwrap();
+        assert_eq!(ssh_key.get_ssh_key().as_deref(), Some("/root/.ssh/example"));
+        assert!(credentials.get("https://example.com/web.git").is_none());
+    }
+
+    #[test]
+    fn unprotected_files_are_ignored_without_echoing_secrets() {
+        let root = TempDir::new().unwrap();
+        let location = root.path().join("credentials");
+        let location = location.to_str().unwrap();
+        fs::write(location, CREDENTIALS).unwrap();
+
+        fs::set_permissions(location, fs::Permissions::from_mode(0o644)).unwrap();
+        let error = Credentials::read(location).err().unwrap();
+        assert!(error.contains("must be owned by root"));
+        assert!(!error.contains("secret"));
+        assert!(Credentials::load(location).get_hosts().is_empty());
+
+        fs::set_permissions(location, fs::Permissions::from_mode(0o600)).unwrap();
+        if fs::metadata(location).unwrap().uid() == 0 {
+            std::os::unix::fs::chown(location, Some(1000), None).unwrap();
+        }
+        let error = Credentials::read(location).err().unwrap();
+        assert!(error.contains("must be owned by root"));
+        assert!(!error.contains("secret"));
+        assert!(Credentials::load(location).get_hosts().is_empty());
+    }
+
+    #[test]
+    fn missing_files_mean_no_credentials() {
+        let root = TempDir::new().unwrap();
+        let location = root.path().join("credentials");
+
+        assert!(Credentials::read(location.to_str().unwrap())
+            .unwrap()
+            .get_hosts()
+            .is_empty());
+    }
+}
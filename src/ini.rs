@@ -0,0 +1,118 @@
+pub struct Section {
+    name: String,
+    entries: Vec<(String, String)>,
+}
+
+impl Section {
+    pub fn new(name: String) -> Section {
+        Section {
+            name,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.entries
+            .iter()
+            .find(|(entry_key, _)| entry_key == key)
+            .map(|(_, value)| value.clone())
+    }
+
+    pub fn push(&mut self, key: &str, value: String) {
+        self.entries.push((key.to_string(), value));
+    }
+
+    pub fn get_entries(&self) -> &Vec<(String, String)> {
+        &self.entries
+    }
+}
+
+/// A parsed repo-style file: `key=value` lines before the first `[section]`
+/// form the header, every `[section]` collects the lines below it.
+pub struct Document {
+    header: Section,
+    sections: Vec<Section>,
+}
+
+impl Document {
+    pub fn new() -> Document {
+        Document {
+            header: Section::new(String::new()),
+            sections: Vec::new(),
+        }
+    }
+
+    pub fn parse(content: &str) -> Document {
+        Document::parse_lines(content, false).unwrap_or_else(|_| Document::new())
+    }
+
+    /// Like `parse`, but fails on lines that are neither a section header nor
+    /// a `key=value` pair instead of ignoring them.
+    pub fn parse_strict(content: &str) -> Result<Document, String> {
+        Document::parse_lines(content, true)
+    }
+
+    fn parse_lines(content: &str, strict: bool) -> Result<Document, String> {
+        let mut document = Document::new();
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = line.trim_start_matches('[').trim_end_matches(']');
+                document
+                    .sections
+                    .push(Section::new(name.trim().to_string()));
+            } else if let Some((key, value)) = line.split_once('=') {
+                let section = match document.sections.last_mut() {
+                    Some(section) => section,
+                    None => &mut document.header,
+                };
+                section.push(key.trim(), value.trim().to_string());
+            } else if strict {
+                return Err(format!("Invalid line {}: {}", index + 1, line));
+            }
+        }
+
+        Ok(document)
+    }
+
+    pub fn get_header(&self) -> &Section {
+        &self.header
+    }
+
+    pub fn get_sections(&self) -> &Vec<Section> {
+        &self.sections
+    }
+
+    pub fn push_section(&mut self, section: Section) {
+        self.sections.push(section);
+    }
+}
+
+impl std::fmt::Display for Document {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (key, value) in &self.header.entries {
+            writeln!(f, "{}={}", key, value)?;
+        }
+
+        for (index, section) in self.sections.iter().enumerate() {
+            if index > 0 || !self.header.entries.is_empty() {
+                writeln!(f)?;
+            }
+            writeln!(f, "[{}]", section.name)?;
+            for (key, value) in &section.entries {
+                writeln!(f, "{}={}", key, value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,265 @@
+use std::fs;
+
+use crate::{
+    ini::{Document, Section},
+    packages,
+    plugin::PluginType,
+};
+
+#[derive(Debug, Clone)]
+pub struct InstalledPlugin {
+    name: String,
+    repo: Option<String>,
+    git_ref: Option<String>,
+    commit: Option<String>,
+    sha256: Option<String>,
+    packages: Vec<String>,
+    origin: Option<(PluginType, String)>,
+    linked: bool,
+    submodules: Vec<(String, String)>,
+    collections: Vec<String>,
+    auto: bool,
+}
+
+const SUBMODULE_PREFIX: &str = "submodule.";
+
+impl InstalledPlugin {
+    pub fn new(name: String, repo: Option<String>) -> InstalledPlugin {
+        InstalledPlugin {
+            name,
+            repo,
+            git_ref: None,
+            commit: None,
+            sha256: None,
+            packages: Vec::new(),
+            origin: None,
+            linked: false,
+            submodules: Vec::new(),
+            collections: Vec::new(),
+            auto: false,
+        }
+    }
+
+    fn from_section(section: &Section) -> InstalledPlugin {
+        let mut plugin = InstalledPlugin::new(section.get_name(), section.get("repo"));
+        plugin.git_ref = section.get("ref");
+        plugin.commit = section.get("commit");
+        plugin.sha256 = section.get("sha256");
+        plugin.packages = packages::split_packages(&section.get("packages").unwrap_or_default());
+        plugin.origin = section
+            .get("type")
+            .and_then(|x| PluginType::parse(&x))
+            .zip(section.get("origin"));
+        plugin.linked = section.get("linked").is_some_and(|x| x == "true");
+        plugin.submodules = section
+            .get_entries()
+            .iter()
+            .filter_map(|(key, value)| {
+                key.strip_prefix(SUBMODULE_PREFIX)
+                    .map(|x| (x.to_string(), value.clone()))
+            })
+            .collect();
+        plugin.collections = section
+            .get("collections")
+            .unwrap_or_default()
+            .split(',')
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+            .collect();
+        plugin.auto = section.get("auto").is_some_and(|x| x == "true");
+        plugin
+    }
+
+    fn to_section(&self) -> Section {
+        let mut section = Section::new(self.name.clone());
+        if let Some(repo) = &self.repo {
+            section.push("repo", repo.clone());
+        }
+        if let Some(git_ref) = &self.git_ref {
+            section.push("ref", git_ref.clone());
+        }
+        if let Some(commit) = &self.commit {
+            section.push("commit", commit.clone());
+        }
+        if let Some(sha256) = &self.sha256 {
+            section.push("sha256", sha256.clone());
+        }
+        if !self.packages.is_empty() {
+            section.push("packages", self.packages.join(","));
+        }
+        if let Some((plugin_type, origin)) = &self.origin {
+            section.push("type", plugin_type.to_string().to_lowercase());
+            section.push("origin", origin.clone());
+        }
+        if self.linked {
+            section.push("linked", String::from("true"));
+        }
+        if !self.collections.is_empty() {
+            section.push("collections", self.collections.join(","));
+        }
+        if self.auto {
+            section.push("auto", String::from("true"));
+        }
+        for (path, commit) in &self.submodules {
+            section.push(&format!("{}{}", SUBMODULE_PREFIX, path), commit.clone());
+        }
+        section
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_repo(&self) -> Option<String> {
+        self.repo.clone()
+    }
+
+    /// Ref chosen with `install name@ref`, which overrides the ref of the
+    /// repo entry on upgrades.
+    pub fn get_git_ref(&self) -> Option<String> {
+        self.git_ref.clone()
+    }
+
+    pub fn set_git_ref(&mut self, git_ref: Option<String>) {
+        self.git_ref = git_ref;
+    }
+
+    /// Commit the plugin was checked out at by the last install or upgrade.
+    pub fn get_commit(&self) -> Option<String> {
+        self.commit.clone()
+    }
+
+    pub fn set_commit(&mut self, commit: Option<String>) {
+        self.commit = commit;
+    }
+
+    /// Checksum of the archive an archive plugin was last installed from.
+    pub fn get_sha256(&self) -> Option<String> {
+        self.sha256.clone()
+    }
+
+    pub fn set_sha256(&mut self, sha256: Option<String>) {
+        self.sha256 = sha256;
+    }
+
+    /// Debian packages the plugin needed when it was installed.
+    pub fn get_packages(&self) -> Vec<String> {
+        self.packages.clone()
+    }
+
+    pub fn set_packages(&mut self, packages: Vec<String>) {
+        self.packages = packages;
+    }
+
+    /// Type and location of an ad-hoc plugin, installed from a path or url
+    /// instead of a repo entry.
+    pub fn get_origin(&self) -> Option<(PluginType, String)> {
+        self.origin.clone()
+    }
+
+    pub fn set_origin(&mut self, origin: Option<(PluginType, String)>) {
+        self.origin = origin;
+    }
+
+    /// Whether the plugin folder is a link to the origin of the plugin, made
+    /// with `link`.
+    pub fn is_linked(&self) -> bool {
+        self.linked
+    }
+
+    pub fn set_linked(&mut self, linked: bool) {
+        self.linked = linked;
+    }
+
+    /// Paths and commits of the checked out submodules, stored as
+    /// `submodule.<path>=<commit>`.
+    pub fn get_submodules(&self) -> Vec<(String, String)> {
+        self.submodules.clone()
+    }
+
+    pub fn set_submodules(&mut self, submodules: Vec<(String, String)>) {
+        self.submodules = submodules;
+    }
+
+    /// Collections the plugin was installed as a member of.
+    pub fn get_collections(&self) -> Vec<String> {
+        self.collections.clone()
+    }
+
+    pub fn set_collections(&mut self, collections: Vec<String>) {
+        self.collections = collections;
+    }
+
+    /// Whether the plugin was only installed as a member of a collection, it
+    /// is removed with the last collection needing it.
+    pub fn is_auto(&self) -> bool {
+        self.auto
+    }
+
+    pub fn set_auto(&mut self, auto: bool) {
+        self.auto = auto;
+    }
+}
+
+/// State of the installed plugins, stored in the installed cache file. Older
+/// caches only contain one plugin name per line, those are read as plugins
+/// without a recorded repo.
+pub struct InstalledCache {
+    location: String,
+    plugins: Vec<InstalledPlugin>,
+}
+
+impl InstalledCache {
+    pub fn load(location: &str) -> Result<InstalledCache, String> {
+        let content = fs::read_to_string(location).map_err(|e| e.to_string())?;
+
+        let plugins = if content.lines().any(|x| x.trim().starts_with('[')) {
+            Document::parse(&content)
+                .get_sections()
+                .iter()
+                .map(InstalledPlugin::from_section)
+                .collect()
+        } else {
+            content
+                .lines()
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty())
+                .map(|x| InstalledPlugin::new(x.to_string(), None))
+                .collect()
+        };
+
+        Ok(InstalledCache {
+            location: location.to_string(),
+            plugins,
+        })
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let mut document = Document::new();
+        for plugin in &self.plugins {
+            document.push_section(plugin.to_section());
+        }
+
+        fs::write(&self.location, document.to_string()).map_err(|e| e.to_string())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&InstalledPlugin> {
+        self.plugins.iter().find(|x| x.name == name)
+    }
+
+    pub fn get_plugins(&self) -> &Vec<InstalledPlugin> {
+        &self.plugins
+    }
+
+    /// Adds the plugin, replacing the previous entry with the same name.
+    pub fn insert(&mut self, plugin: InstalledPlugin) {
+        self.remove(&plugin.get_name());
+        self.plugins.push(plugin);
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let length = self.plugins.len();
+        self.plugins.retain(|x| x.name != name);
+        length != self.plugins.len()
+    }
+}
@@ -1,5 +1,26 @@
+mod archive;
+mod atomic;
+mod builder;
+mod checksum;
+mod collection;
+mod credentials;
+mod ini;
+mod installed;
+mod manifest;
+mod mirror;
+mod packages;
+mod platform;
 mod plugin;
 mod plugin_manager;
+mod remote;
+mod repo;
+mod repo_state;
+mod requirements;
+#[cfg(test)]
+mod test_server;
+mod trust;
+mod vcs;
+mod watch;
 
 use std::env::args;
 
@@ -16,6 +37,13 @@ fn main() {
                 "upgrade" => upgrade(&plug_manager),
                 "uninstall" => uninstall(&plug_manager),
                 "list" => list(&plug_manager),
+                "info" => info(&plug_manager),
+                "repo" => repo(&plug_manager),
+                "key" => key(&plug_manager),
+                "packages" => packages(&plug_manager),
+                "link" => link(&plug_manager),
+                "unlink" => unlink(&plug_manager),
+                "dev" => dev(&plug_manager),
 
                 _ => print_help(),
             }
@@ -28,6 +56,45 @@ fn list(plug_manager: &PluginManager) {
     println!("{}", plug_manager.list());
 }
 
+fn info(plug_manager: &PluginManager) {
+    plug_manager.info(args());
+}
+
+fn repo(plug_manager: &PluginManager) {
+    match args().nth(2).as_deref() {
+        Some("list") => print!("{}", plug_manager.list_repos()),
+        _ => print_help(),
+    }
+}
+
+fn key(plug_manager: &PluginManager) {
+    match args().nth(2).as_deref() {
+        Some("add") => plug_manager.add_key(args()),
+        Some("list") => print!("{}", plug_manager.list_keys()),
+        Some("remove") => plug_manager.remove_key(args()),
+        _ => print_help(),
+    }
+}
+
+fn link(plug_manager: &PluginManager) {
+    plug_manager.link(args());
+}
+
+fn unlink(plug_manager: &PluginManager) {
+    plug_manager.unlink(args());
+}
+
+fn dev(plug_manager: &PluginManager) {
+    match args().nth(2).as_deref() {
+        Some("watch") => plug_manager.watch(args()),
+        _ => print_help(),
+    }
+}
+
+fn packages(plug_manager: &PluginManager) {
+    print!("{}", plug_manager.list_packages());
+}
+
 fn update(plug_manager: &mut PluginManager) {
     plug_manager.update();
 }
@@ -45,5 +112,5 @@ fn install(plug_manager: &mut PluginManager) {
 }
 
 fn print_help() {
-    println!("RPi mesh's plugin manager\n\nUSAGE:\n\trpi-mesh-plugin-manager [OPTIONS] [PLUGIN NAME]\n\nCOMMANDS:\n\tinstall\t\t\tInstalls the specified plugin\n\tupdate\t\t\tUpdates repositories\n\tupgrade\t\t\tUpgrades specific plugins. Upgrades all plugins when none are specified.\n\tuninstall\t\tUninstalls specified plugin\n\tlist\t\t\tDisplays a list of available plugins\n\thelp\t\t\tDisplays this text");
+    println!("RPi mesh's plugin manager\n\nUSAGE:\n\trpi-mesh-plugin-manager [OPTIONS] [PLUGIN NAME]\n\nCOMMANDS:\n\tinstall\t\t\tInstalls the specified plugins or collections, use <repo>/<plugin> to choose the repo and <plugin>@<ref> to choose a git branch, tag or commit. Plugins can also be installed from a path, git url or archive\n\tupdate\t\t\tUpdates repositories\n\tupgrade\t\t\tUpgrades specific plugins. Upgrades all plugins when none are specified. Use <plugin>@<ref> to switch the git ref\n\tuninstall\t\tUninstalls specified plugins or collections\n\tlink <path>\t\tInstalls a local plugin as a link to its folder, for developing it\n\tunlink <plugin>\t\tRemoves a linked plugin without touching its folder\n\tdev watch <plugin>\tSyncs changes of a local plugin into its installed copy while it runs\n\tlist\t\t\tDisplays a list of available plugins\n\tinfo\t\t\tDisplays details of the specified plugins and the repos defining them\n\trepo list\t\tDisplays the repos in precedence order with their age\n\tkey add <file>\t\tTrusts a minisign public key, optionally limited with --repos <repo,...> and --expires <YYYY-MM-DD>\n\tkey list\t\tDisplays the trusted keys\n\tkey remove <id>\t\tRemoves a trusted key\n\tpackages\t\tDisplays the system packages needed by the installed plugins\n\thelp\t\t\tDisplays this text");
 }
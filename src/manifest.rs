@@ -0,0 +1,135 @@
+use std::{
+    fs,
+    path::{Component, Path},
+    time::Duration,
+};
+
+use crate::{ini::Document, packages, requirements::Requirements};
+
+/// File in the root of a plugin describing it, with `key=value` lines.
+pub const MANIFEST_FILE: &str = "plugin.conf";
+
+/// Time a build command may run when the manifest sets no `build_timeout`.
+const DEFAULT_BUILD_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Plugin names end up in paths, so they may not contain a path separator or
+/// be a relative path component. A leading `.` is reserved for the staging
+/// folders next to the plugins.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.contains('/')
+        && !name.contains(char::is_whitespace)
+}
+
+/// Manifest of a plugin, a plugin without a manifest file has an empty one.
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    name: Option<String>,
+    reload: Option<String>,
+    preserve: Vec<String>,
+    build: Option<String>,
+    build_timeout: Option<Duration>,
+    requirements: Requirements,
+    packages: Vec<String>,
+}
+
+impl Manifest {
+    pub fn load(plugin_path: &Path) -> Result<Manifest, String> {
+        let location = plugin_path.join(MANIFEST_FILE);
+        if !location.is_file() {
+            return Ok(Manifest::default());
+        }
+
+        let content = fs::read_to_string(&location).map_err(|e| e.to_string())?;
+        let document = Document::parse_strict(&content)
+            .map_err(|e| format!("Invalid {}! {}", MANIFEST_FILE, e))?;
+        let header = document.get_header();
+
+        let name = header.get("name");
+        if name.as_deref().is_some_and(|x| !is_valid_name(x)) {
+            return Err(format!("Invalid plugin name in {}!", MANIFEST_FILE));
+        }
+
+        let preserve: Vec<String> = header
+            .get("preserve")
+            .unwrap_or_default()
+            .split(',')
+            .map(|x| x.trim().trim_end_matches('/').to_string())
+            .filter(|x| !x.is_empty())
+            .collect();
+        if preserve.iter().any(|x| {
+            Path::new(x)
+                .components()
+                .any(|x| !matches!(x, Component::Normal(_)))
+        }) {
+            return Err(format!("Invalid preserved path in {}!", MANIFEST_FILE));
+        }
+
+        let build_timeout = match header.get("build_timeout") {
+            Some(x) => match x.parse::<u64>() {
+                Ok(seconds) if seconds > 0 => Some(Duration::from_secs(seconds)),
+                _ => return Err(format!("Invalid build timeout in {}!", MANIFEST_FILE)),
+            },
+            None => None,
+        };
+
+        let packages = packages::split_packages(&header.get("packages").unwrap_or_default());
+        if let Some(package) = packages.iter().find(|x| !packages::is_valid_package(x)) {
+            return Err(format!(
+                "Invalid package '{}' in {}!",
+                package, MANIFEST_FILE
+            ));
+        }
+
+        Ok(Manifest {
+            name,
+            reload: header.get("reload"),
+            preserve,
+            build: header.get("build").filter(|x| !x.is_empty()),
+            build_timeout,
+            requirements: Requirements::from_section(header),
+            packages,
+        })
+    }
+
+    pub fn get_name(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    /// Shell command run in the plugin folder after `dev watch` synced
+    /// changes, to reload the plugin.
+    pub fn get_reload(&self) -> Option<String> {
+        self.reload.clone()
+    }
+
+    /// Paths relative to the plugin folder that upgrades keep even when the
+    /// new version does not have them, like data the plugin writes itself.
+    pub fn get_preserve(&self) -> Vec<String> {
+        self.preserve.clone()
+    }
+
+    /// Shell command run in the plugin folder before the plugin is
+    /// activated, like `npm ci && npm run build`.
+    pub fn get_build(&self) -> Option<String> {
+        self.build.clone()
+    }
+
+    /// Time after which the build command is killed, `build_timeout` in
+    /// seconds.
+    pub fn get_build_timeout(&self) -> Duration {
+        self.build_timeout.unwrap_or(DEFAULT_BUILD_TIMEOUT)
+    }
+
+    /// Hardware and resources the plugin needs, checked on install like the
+    /// requirements of its repo entry.
+    pub fn get_requirements(&self) -> &Requirements {
+        &self.requirements
+    }
+
+    /// Debian packages the plugin needs, used for plugins installed from a
+    /// path or url as they have no repo entry.
+    pub fn get_packages(&self) -> Vec<String> {
+        self.packages.clone()
+    }
+}
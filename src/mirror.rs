@@ -0,0 +1,374 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File, Metadata},
+    io::{self, Read},
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use fs_extra::dir::CopyOptions;
+
+pub enum Sync {
+    Updated(String),
+    Removed(String),
+    Preserved(String),
+}
+
+impl std::fmt::Display for Sync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Sync::Updated(path) => write!(f, "updated: {}", path),
+            Sync::Removed(path) => write!(f, "removed: {}", path),
+            Sync::Preserved(path) => write!(f, "preserved: {}", path),
+        }
+    }
+}
+
+/// Whether a path is in `preserve`, inside a preserved folder, or a folder
+/// holding a preserved path.
+fn is_preserved(relative: &Path, preserve: &[String]) -> bool {
+    preserve
+        .iter()
+        .any(|x| relative.starts_with(x) || Path::new(x).starts_with(relative))
+}
+
+/// Makes one path of the target folder match the source folder: files and
+/// folders are copied over, links recreated, and paths missing from the
+/// source are removed unless they are in `preserve` or inside a preserved
+/// folder.
+pub fn mirror_path(
+    source: &Path,
+    target: &Path,
+    relative: &Path,
+    preserve: &[String],
+) -> Result<Sync, String> {
+    let source_path = source.join(relative);
+    let target_path = target.join(relative);
+    let name = relative.display().to_string();
+
+    let metadata = match fs::symlink_metadata(&source_path) {
+        Ok(metadata) => metadata,
+        Err(_) => {
+            if preserve.iter().any(|x| relative.starts_with(x)) {
+                return Ok(Sync::Preserved(name));
+            }
+            match fs::symlink_metadata(&target_path) {
+                Ok(x) if x.is_dir() && is_preserved(relative, preserve) => {
+                    remove_unpreserved(target, relative, preserve)
+                }
+                Ok(x) if x.is_dir() => fs::remove_dir_all(&target_path).map_err(|e| e.to_string()),
+                Ok(_) => fs::remove_file(&target_path).map_err(|e| e.to_string()),
+                Err(_) => Ok(()),
+            }?;
+            return Ok(Sync::Removed(name));
+        }
+    };
+
+    if let Ok(existing) = fs::symlink_metadata(&target_path) {
+        if existing.is_dir() != metadata.is_dir() || existing.file_type().is_symlink() {
+            if existing.is_dir() {
+                fs::remove_dir_all(&target_path)
+            } else {
+                fs::remove_file(&target_path)
+            }
+            .map_err(|e| e.to_string())?;
+        }
+    }
+    if let Some(parent) = target_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+
+    if metadata.file_type().is_symlink() {
+        let link = fs::read_link(&source_path).map_err(|e| e.to_string())?;
+        symlink(link, &target_path).map_err(|e| e.to_string())?;
+    } else if metadata.is_dir() {
+        let mut options = CopyOptions::new();
+        options.overwrite = true;
+        options.content_only = true;
+        fs_extra::dir::copy(&source_path, &target_path, &options).map_err(|e| e.to_string())?;
+    } else {
+        fs::copy(&source_path, &target_path).map_err(|e| e.to_string())?;
+    }
+    Ok(Sync::Updated(name))
+}
+
+/// What mirroring a folder changed, counting files and links.
+#[derive(Debug, Default)]
+pub struct MirrorReport {
+    added: usize,
+    changed: usize,
+    removed: usize,
+}
+
+impl std::fmt::Display for MirrorReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} added, {} changed, {} removed",
+            self.added, self.changed, self.removed
+        )
+    }
+}
+
+/// Makes the target folder an exact copy of the source folder. Only files
+/// whose content, permissions or link target differ are copied, and paths
+/// missing from the source are removed unless they are in `preserve` or
+/// inside a preserved folder.
+pub fn mirror_tree(
+    source: &Path,
+    target: &Path,
+    preserve: &[String],
+) -> Result<MirrorReport, String> {
+    let mut report = MirrorReport::default();
+
+    let mut source_entries = Vec::new();
+    collect_entries(source, Path::new(""), &mut source_entries)?;
+    for relative in &source_entries {
+        let source_path = source.join(relative);
+        let target_path = target.join(relative);
+        let metadata = fs::symlink_metadata(&source_path).map_err(|e| e.to_string())?;
+        let existing = fs::symlink_metadata(&target_path).ok();
+
+        if metadata.is_dir() {
+            if existing.as_ref().is_some_and(|x| !x.is_dir()) {
+                fs::remove_file(&target_path).map_err(|e| e.to_string())?;
+                report.removed += 1;
+            }
+            if existing.is_none_or(|x| !x.is_dir()) {
+                fs::create_dir_all(&target_path).map_err(|e| e.to_string())?;
+            }
+            continue;
+        }
+
+        match existing {
+            Some(existing) if is_same(&source_path, &metadata, &target_path, &existing) => {}
+            Some(existing) => {
+                if existing.is_dir() {
+                    fs::remove_dir_all(&target_path).map_err(|e| e.to_string())?;
+                } else {
+                    fs::remove_file(&target_path).map_err(|e| e.to_string())?;
+                }
+                copy_entry(&source_path, &metadata, &target_path)?;
+                report.changed += 1;
+            }
+            None => {
+                copy_entry(&source_path, &metadata, &target_path)?;
+                report.added += 1;
+            }
+        }
+    }
+
+    let source_entries: HashSet<PathBuf> = source_entries.into_iter().collect();
+    let mut target_entries = Vec::new();
+    collect_entries(target, Path::new(""), &mut target_entries)?;
+    // Children are listed after their folder, so going backwards empties
+    // folders before they are removed.
+    for relative in target_entries.iter().rev() {
+        if source_entries.contains(relative) || is_preserved(relative, preserve) {
+            continue;
+        }
+
+        let target_path = target.join(relative);
+        let metadata = fs::symlink_metadata(&target_path).map_err(|e| e.to_string())?;
+        if metadata.is_dir() {
+            fs::remove_dir(&target_path).map_err(|e| e.to_string())?;
+        } else {
+            fs::remove_file(&target_path).map_err(|e| e.to_string())?;
+            report.removed += 1;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Lists every path below a folder relative to it, folders before their
+/// content. Links are listed but not followed.
+fn collect_entries(root: &Path, relative: &Path, entries: &mut Vec<PathBuf>) -> Result<(), String> {
+    let mut names: Vec<_> = fs::read_dir(root.join(relative))
+        .map_err(|e| e.to_string())?
+        .map(|x| x.map(|x| x.file_name()))
+        .collect::<Result<_, _>>()
+        .map_err(|e| e.to_string())?;
+    names.sort();
+
+    for name in names {
+        let path = relative.join(name);
+        entries.push(path.clone());
+        if fs::symlink_metadata(root.join(&path)).is_ok_and(|x| x.is_dir()) {
+            collect_entries(root, &path, entries)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes a folder of the target except for the preserved paths in it.
+fn remove_unpreserved(target: &Path, relative: &Path, preserve: &[String]) -> Result<(), String> {
+    let mut entries = Vec::new();
+    collect_entries(target, relative, &mut entries)?;
+    for relative in entries.iter().rev() {
+        if is_preserved(relative, preserve) {
+            continue;
+        }
+        let path = target.join(relative);
+        if fs::symlink_metadata(&path).is_ok_and(|x| x.is_dir()) {
+            fs::remove_dir(&path)
+        } else {
+            fs::remove_file(&path)
+        }
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Files of the same size and modification time are taken as the same,
+/// like copies made by `copy_entry`. Others are compared chunk by chunk.
+fn is_same(
+    source: &Path,
+    source_metadata: &Metadata,
+    target: &Path,
+    target_metadata: &Metadata,
+) -> bool {
+    if source_metadata.file_type() != target_metadata.file_type() {
+        return false;
+    }
+    if source_metadata.file_type().is_symlink() {
+        return fs::read_link(source).ok() == fs::read_link(target).ok();
+    }
+    if source_metadata.len() != target_metadata.len()
+        || source_metadata.permissions() != target_metadata.permissions()
+    {
+        return false;
+    }
+    if source_metadata
+        .modified()
+        .is_ok_and(|x| target_metadata.modified().is_ok_and(|y| x == y))
+    {
+        return true;
+    }
+
+    is_same_content(source, target).unwrap_or(false)
+}
+
+fn is_same_content(source: &Path, target: &Path) -> io::Result<bool> {
+    let mut source = File::open(source)?;
+    let mut target = File::open(target)?;
+    let mut source_buffer = vec![0; 64 * 1024];
+    let mut target_buffer = vec![0; 64 * 1024];
+    loop {
+        let read = read_full(&mut source, &mut source_buffer)?;
+        if read != read_full(&mut target, &mut target_buffer)? {
+            return Ok(false);
+        }
+        if read == 0 {
+            return Ok(true);
+        }
+        if source_buffer[..read] != target_buffer[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Reads until the buffer is full or the file ends, so chunks of both files
+/// line up.
+fn read_full(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+/// Copies a file or recreates a link. Copied files keep the modification
+/// time of the source, so unchanged files are recognized without reading
+/// them.
+fn copy_entry(source: &Path, metadata: &Metadata, target: &Path) -> Result<(), String> {
+    if metadata.file_type().is_symlink() {
+        let link = fs::read_link(source).map_err(|e| e.to_string())?;
+        symlink(link, target).map_err(|e| e.to_string())
+    } else {
+        fs::copy(source, target).map_err(|e| e.to_string())?;
+        if let Ok(modified) = metadata.modified() {
+            File::options()
+                .write(true)
+                .open(target)
+                .and_then(|x| x.set_modified(modified))
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn set_modified(path: &Path, modified: SystemTime) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    #[test]
+    fn unchanged_files_are_not_copied_again() {
+        let root = tempfile::tempdir().unwrap();
+        let (source, target) = (root.path().join("source"), root.path().join("target"));
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(&target).unwrap();
+        fs::write(source.join("index.html"), "aaaa").unwrap();
+
+        let report = mirror_tree(&source, &target, &[]).unwrap();
+        assert_eq!((report.added, report.changed), (1, 0));
+        let report = mirror_tree(&source, &target, &[]).unwrap();
+        assert_eq!((report.added, report.changed), (0, 0));
+
+        // Same size, but another time: compared by content.
+        fs::write(source.join("index.html"), "bbbb").unwrap();
+        set_modified(
+            &source.join("index.html"),
+            SystemTime::now() + Duration::from_secs(60),
+        );
+        let report = mirror_tree(&source, &target, &[]).unwrap();
+        assert_eq!(report.changed, 1);
+        assert_eq!(fs::read(target.join("index.html")).unwrap(), b"bbbb");
+
+        set_modified(&target.join("index.html"), SystemTime::UNIX_EPOCH);
+        let report = mirror_tree(&source, &target, &[]).unwrap();
+        assert_eq!(report.changed, 0);
+    }
+
+    #[test]
+    fn mirroring_a_path_keeps_preserved_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let (source, target) = (root.path().join("source"), root.path().join("target"));
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(target.join("data/cache")).unwrap();
+        fs::write(target.join("data/db"), "rows").unwrap();
+        fs::write(target.join("data/cache/x"), "x").unwrap();
+        fs::write(target.join("state"), "on").unwrap();
+        fs::write(target.join("old.html"), "old").unwrap();
+        let preserve = [String::from("data/db"), String::from("state")];
+
+        let sync = |relative: &str| {
+            mirror_path(&source, &target, Path::new(relative), &preserve)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(sync("state"), "preserved: state");
+        assert_eq!(sync("data/db"), "preserved: data/db");
+        assert_eq!(sync("data"), "removed: data");
+        assert_eq!(sync("old.html"), "removed: old.html");
+
+        assert_eq!(fs::read(target.join("data/db")).unwrap(), b"rows");
+        assert!(target.join("state").is_file());
+        assert!(!target.join("data/cache").exists());
+        assert!(!target.join("old.html").exists());
+    }
+}
@@ -0,0 +1,84 @@
+use std::{collections::HashSet, fs, path::Path, process::Command};
+
+/// Debian package names: lowercase letters, digits, `+`, `-` and `.`,
+/// starting with a letter or digit.
+pub fn is_valid_package(package: &str) -> bool {
+    package.len() >= 2
+        && package
+            .chars()
+            .next()
+            .is_some_and(|x| x.is_ascii_lowercase() || x.is_ascii_digit())
+        && package
+            .chars()
+            .all(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || "+-.".contains(x))
+}
+
+/// Splits a comma separated list of packages.
+pub fn split_packages(packages: &str) -> Vec<String> {
+    packages
+        .split(',')
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+/// The installed packages in the dpkg status database, including the
+/// virtual packages they provide.
+pub struct PackageDatabase {
+    installed: HashSet<String>,
+}
+
+impl PackageDatabase {
+    pub fn load(root_prefix: &str) -> PackageDatabase {
+        let location = Path::new("/").join(root_prefix).join("var/lib/dpkg/status");
+        let content = fs::read_to_string(location).unwrap_or_default();
+
+        let mut installed = HashSet::new();
+        for paragraph in content.split("\n\n") {
+            let field = |name: &str| {
+                paragraph
+                    .lines()
+                    .find_map(|x| x.strip_prefix(name))
+                    .map(|x| x.trim().to_string())
+            };
+            if !field("Status:").is_some_and(|x| x.ends_with(" installed")) {
+                continue;
+            }
+
+            if let Some(package) = field("Package:") {
+                installed.insert(package);
+            }
+            // Provides lists packages with optional versions, like
+            // `mail-transport-agent, awk (= 1.0)`.
+            for provided in split_packages(&field("Provides:").unwrap_or_default()) {
+                if let Some(name) = provided.split_whitespace().next() {
+                    installed.insert(name.to_string());
+                }
+            }
+        }
+
+        PackageDatabase { installed }
+    }
+
+    pub fn is_installed(&self, package: &str) -> bool {
+        self.installed.contains(package)
+    }
+}
+
+/// Runs the configured package command, like `apt-get install -y`, with the
+/// packages appended.
+pub fn install(command: &str, packages: &[String]) -> Result<(), String> {
+    let mut parts = command.split_whitespace();
+    let program = parts.next().ok_or("No package command set")?;
+
+    let status = Command::new(program)
+        .args(parts)
+        .args(packages)
+        .status()
+        .map_err(|e| e.to_string())?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("Package command failed with {}", status))
+    }
+}
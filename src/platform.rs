@@ -0,0 +1,60 @@
+use std::process::Command;
+
+/// Name of an architecture as used in `location.<os>-<arch>` keys. Raspberry
+/// Pi OS often runs a 32 bit userland on a 64 bit kernel, so a 32 bit build of
+/// the plugin manager on an `aarch64` kernel counts as `armv7`.
+fn normalize_arch(machine: &str, is_32bit_userland: bool) -> String {
+    match machine {
+        "armv6l" | "armv6" => String::from("armv6"),
+        "armv7l" | "armv7" | "armv8l" => String::from("armv7"),
+        "aarch64" | "arm64" if is_32bit_userland => String::from("armv7"),
+        "aarch64" | "arm64" => String::from("aarch64"),
+        "i386" | "i486" | "i586" | "i686" => String::from("i686"),
+        "amd64" => String::from("x86_64"),
+        _ => machine.to_string(),
+    }
+}
+
+/// Detects the platform of the node as `<os>-<arch>`, for example
+/// `linux-armv6` on a Pi Zero.
+pub fn detect() -> String {
+    let machine = Command::new("uname")
+        .arg("-m")
+        .output()
+        .ok()
+        .filter(|x| x.status.success())
+        .map(|x| String::from_utf8_lossy(&x.stdout).trim().to_string())
+        .filter(|x| !x.is_empty())
+        .unwrap_or(std::env::consts::ARCH.to_string());
+
+    format!(
+        "{}-{}",
+        std::env::consts::OS,
+        normalize_arch(&machine, cfg!(target_pointer_width = "32"))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn machine_names_are_normalized() {
+        assert_eq!(normalize_arch("armv6l", false), "armv6");
+        assert_eq!(normalize_arch("armv7l", false), "armv7");
+        assert_eq!(normalize_arch("armv8l", false), "armv7");
+        assert_eq!(normalize_arch("aarch64", false), "aarch64");
+        assert_eq!(normalize_arch("arm64", false), "aarch64");
+        assert_eq!(normalize_arch("i586", false), "i686");
+        assert_eq!(normalize_arch("amd64", false), "x86_64");
+        assert_eq!(normalize_arch("riscv64", false), "riscv64");
+    }
+
+    #[test]
+    fn a_32bit_userland_on_a_64bit_kernel_is_armv7() {
+        assert_eq!(normalize_arch("aarch64", true), "armv7");
+        assert_eq!(normalize_arch("arm64", true), "armv7");
+        assert_eq!(normalize_arch("armv7l", true), "armv7");
+        assert_eq!(normalize_arch("x86_64", true), "x86_64");
+    }
+}
@@ -1,43 +1,225 @@
-use std::fmt::{self, Display};
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::Path,
+};
+
+use reqwest::Url;
+
+use crate::{archive::ArchiveFormat, ini::Section, packages, requirements::Requirements};
+
+/// Repo of plugins installed from a path or url instead of a repo entry.
+pub const AD_HOC_REPO: &str = "ad-hoc";
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum PluginType {
     Collection,
     Repo,
     Local,
+    Archive,
+}
+
+impl PluginType {
+    pub fn parse(plugin_type: &str) -> Option<PluginType> {
+        match plugin_type {
+            "repo" => Some(PluginType::Repo),
+            "local" => Some(PluginType::Local),
+            "collection" => Some(PluginType::Collection),
+            "archive" => Some(PluginType::Archive),
+            _ => None,
+        }
+    }
 }
 
 impl Display for PluginType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let plugin_type;
-        match self {
-            PluginType::Collection => plugin_type = String::from("Collection"),
-            PluginType::Repo => plugin_type = String::from("Repo"),
-            PluginType::Local => plugin_type = String::from("Local"),
-        }
+        let plugin_type = match self {
+            PluginType::Collection => String::from("Collection"),
+            PluginType::Repo => String::from("Repo"),
+            PluginType::Local => String::from("Local"),
+            PluginType::Archive => String::from("Archive"),
+        };
 
         write!(f, "{}", plugin_type)
     }
 }
 
-#[derive(Debug)]
+/// Splits a `repo/plugin` name into its repo and plugin parts, a plain
+/// plugin name has no repo part.
+pub fn split_qualified_name(name: &str) -> (Option<&str>, &str) {
+    match name.split_once('/') {
+        Some((repo, plugin)) => (Some(repo), plugin),
+        None => (None, name),
+    }
+}
+
+/// Splits a `plugin@ref` name into its plugin and git ref parts.
+pub fn split_ref(name: &str) -> (&str, Option<&str>) {
+    match name.split_once('@') {
+        Some((plugin, git_ref)) => (plugin, Some(git_ref)),
+        None => (name, None),
+    }
+}
+
+/// Git refs are passed to git as arguments, so they may not look like an
+/// option.
+pub fn is_valid_ref(git_ref: &str) -> bool {
+    !git_ref.is_empty() && !git_ref.starts_with('-') && !git_ref.contains(char::is_whitespace)
+}
+
+/// Download of a plugin for one platform, from `location.<platform>` and
+/// `sha256.<platform>` keys.
+#[derive(Debug, Clone)]
+pub struct Build {
+    platform: String,
+    location: String,
+    sha256: Option<String>,
+}
+
+impl Build {
+    pub fn get_platform(&self) -> String {
+        self.platform.clone()
+    }
+
+    pub fn get_location(&self) -> String {
+        self.location.clone()
+    }
+}
+
+pub const LOCATION_PREFIX: &str = "location.";
+pub const SHA256_PREFIX: &str = "sha256.";
+
+#[derive(Debug, Clone)]
 pub struct Plugin {
     name: String,
     enabled: bool,
     plugin_type: PluginType,
     location: String,
+    repo: String,
+    sha256: Option<String>,
+    commit: Option<String>,
+    git_ref: Option<String>,
+    subdir: Option<String>,
+    submodules: bool,
+    builds: Vec<Build>,
+    requirements: Requirements,
+    packages: Vec<String>,
 }
 
 impl Plugin {
-    pub fn new(name: String, enabled: bool, plugin_type: PluginType, location: String) -> Plugin {
+    pub fn new(
+        name: String,
+        enabled: bool,
+        plugin_type: PluginType,
+        location: String,
+        repo: String,
+    ) -> Plugin {
         Plugin {
             name,
             enabled,
             plugin_type,
             location,
+            repo,
+            sha256: None,
+            commit: None,
+            git_ref: None,
+            subdir: None,
+            submodules: true,
+            builds: Vec::new(),
+            requirements: Requirements::default(),
+            packages: Vec::new(),
         }
     }
 
+    pub fn from_section(section: &Section, repo: String) -> Plugin {
+        let mut plugin = Plugin::new(
+            section.get_name(),
+            section.get("enabled").is_some_and(|x| x == "true"),
+            section
+                .get("type")
+                .and_then(|x| PluginType::parse(&x))
+                .unwrap_or(PluginType::Repo),
+            section.get("location").unwrap_or_default(),
+            repo,
+        );
+        plugin.sha256 = section.get("sha256");
+        plugin.commit = section.get("commit");
+        plugin.git_ref = section.get("ref");
+        plugin.submodules = section.get("submodules").is_none_or(|x| x != "false");
+        plugin.subdir = section
+            .get("subdir")
+            .map(|x| x.trim_matches('/').to_string());
+        plugin.builds = section
+            .get_entries()
+            .iter()
+            .filter_map(|(key, location)| {
+                let platform = key.strip_prefix(LOCATION_PREFIX)?;
+                Some(Build {
+                    platform: platform.to_string(),
+                    location: location.clone(),
+                    sha256: section.get(&format!("{}{}", SHA256_PREFIX, platform)),
+                })
+            })
+            .collect();
+        plugin.requirements = Requirements::from_section(section);
+        plugin.packages = packages::split_packages(&section.get("packages").unwrap_or_default());
+        plugin
+    }
+
+    /// Builds a plugin from a path or url given instead of a plugin name: a
+    /// local folder, an archive, or a git repository. It is named after its
+    /// location until its manifest is read. Returns `None` for plugin names.
+    pub fn ad_hoc(argument: &str) -> Option<Plugin> {
+        let is_url = argument.contains("://");
+        let is_path = argument.starts_with('/')
+            || argument.starts_with("./")
+            || argument.starts_with("../")
+            || argument == "."
+            || argument == "..";
+        let is_archive = ArchiveFormat::from_location(argument).is_some();
+        let is_git = argument.trim_end_matches('/').ends_with(".git");
+        if !is_url && !is_path && !is_archive && !is_git {
+            return None;
+        }
+
+        let location = if is_url {
+            argument.to_string()
+        } else {
+            fs::canonicalize(argument)
+                .map(|x| x.display().to_string())
+                .unwrap_or(argument.to_string())
+        };
+        let (plugin_type, location) = if is_archive && !is_url {
+            let url = Url::from_file_path(&location).ok()?;
+            (PluginType::Archive, url.to_string())
+        } else if is_archive {
+            (PluginType::Archive, location)
+        } else if is_url || is_git {
+            (PluginType::Repo, location)
+        } else {
+            (PluginType::Local, location)
+        };
+
+        let file_name = Path::new(location.trim_end_matches('/'))
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let name = [".git", ".tar.gz", ".tgz", ".tar.zst", ".tzst", ".zip"]
+            .iter()
+            .fold(file_name.as_str(), |name, extension| {
+                name.strip_suffix(extension).unwrap_or(name)
+            })
+            .to_string();
+
+        Some(Plugin::new(
+            name,
+            true,
+            plugin_type,
+            location,
+            AD_HOC_REPO.to_string(),
+        ))
+    }
+
     pub fn get_plugin_type(&self) -> PluginType {
         self.plugin_type.clone()
     }
@@ -49,6 +231,89 @@ impl Plugin {
     pub fn get_name(&self) -> String {
         self.name.clone()
     }
+
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn get_repo(&self) -> String {
+        self.repo.clone()
+    }
+
+    /// Checksum of the plugin content pinned in the repo, see
+    /// `checksum::sha256_dir`. For archive plugins it is the checksum of the
+    /// archive file.
+    pub fn get_sha256(&self) -> Option<String> {
+        self.sha256.clone()
+    }
+
+    /// Full git commit hash pinned in the repo.
+    pub fn get_commit(&self) -> Option<String> {
+        self.commit.clone()
+    }
+
+    /// Branch, tag or commit a git plugin follows.
+    pub fn get_git_ref(&self) -> Option<String> {
+        self.git_ref.clone()
+    }
+
+    /// Follows another ref, a ref given by the user replaces the commit
+    /// pinned in the repo.
+    pub fn set_git_ref(&mut self, git_ref: Option<String>) {
+        self.git_ref = git_ref;
+        self.commit = None;
+    }
+
+    /// Folder of the git repository holding the plugin, for plugins living in
+    /// a monorepo.
+    pub fn get_subdir(&self) -> Option<String> {
+        self.subdir.clone()
+    }
+
+    /// Platform specific downloads, a plugin without any has a single
+    /// location for all platforms.
+    pub fn get_builds(&self) -> &Vec<Build> {
+        &self.builds
+    }
+
+    /// The plugin with the location and checksum of the build for the
+    /// platform. Without a matching build the generic `location` is used,
+    /// `None` if there is none either.
+    pub fn for_platform(&self, platform: &str) -> Option<Plugin> {
+        match self.builds.iter().find(|x| x.platform == platform) {
+            Some(build) => {
+                let mut plugin = self.clone();
+                plugin.location = build.location.clone();
+                plugin.sha256 = build.sha256.clone();
+                Some(plugin)
+            }
+            None if !self.location.is_empty() || self.builds.is_empty() => Some(self.clone()),
+            None => None,
+        }
+    }
+
+    pub fn get_requirements(&self) -> &Requirements {
+        &self.requirements
+    }
+
+    /// Debian packages the plugin needs.
+    pub fn get_packages(&self) -> Vec<String> {
+        self.packages.clone()
+    }
+
+    pub fn set_packages(&mut self, packages: Vec<String>) {
+        self.packages = packages;
+    }
+
+    /// Whether the submodules of a git plugin are checked out, on unless the
+    /// repo entry sets `submodules=false`.
+    pub fn is_submodules_enabled(&self) -> bool {
+        self.submodules
+    }
 }
 
 impl fmt::Display for Plugin {
@@ -63,3 +328,54 @@ impl fmt::Display for Plugin {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ini::Document;
+
+    fn parse_plugin(content: &str) -> Plugin {
+        let document = Document::parse(content);
+        Plugin::from_section(&document.get_sections()[0], String::from("official"))
+    }
+
+    #[test]
+    fn the_build_for_the_platform_is_picked() {
+        let plugin = parse_plugin(
+            "[meshd]\ntype=archive\n\
+             location.linux-armv7=https://example.org/armv7.tar.gz\n\
+             sha256.linux-armv7=aa\n\
+             location.linux-aarch64=https://example.org/aarch64.tar.gz\n",
+        );
+
+        let build = plugin.for_platform("linux-aarch64").unwrap();
+        assert_eq!(build.get_location(), "https://example.org/aarch64.tar.gz");
+        assert_eq!(build.get_sha256(), None);
+        let build = plugin.for_platform("linux-armv7").unwrap();
+        assert_eq!(build.get_location(), "https://example.org/armv7.tar.gz");
+        assert_eq!(build.get_sha256(), Some(String::from("aa")));
+        assert!(plugin.for_platform("linux-armv6").is_none());
+    }
+
+    #[test]
+    fn the_generic_location_is_the_fallback() {
+        let plugin = parse_plugin(
+            "[meshd]\ntype=archive\n\
+             location=https://example.org/any.tar.gz\n\
+             sha256=bb\n\
+             location.linux-aarch64=https://example.org/aarch64.tar.gz\n",
+        );
+
+        let build = plugin.for_platform("linux-armv6").unwrap();
+        assert_eq!(build.get_location(), "https://example.org/any.tar.gz");
+        assert_eq!(build.get_sha256(), Some(String::from("bb")));
+        let build = plugin.for_platform("linux-aarch64").unwrap();
+        assert_eq!(build.get_location(), "https://example.org/aarch64.tar.gz");
+
+        let plugin = parse_plugin("[tool]\nlocation=https://example.org/tool.git\n");
+        assert_eq!(
+            plugin.for_platform("linux-armv6").unwrap().get_location(),
+            "https://example.org/tool.git"
+        );
+    }
+}
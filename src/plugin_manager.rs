@@ -1,22 +1,96 @@
 use std::{
-    collections::HashMap,
-    fs::{self, read_dir, OpenOptions},
-    io::prelude::*,
+    collections::{hash_map::Entry, HashMap},
+    fs::{self, read_dir},
     path::Path,
     process::{exit, Command},
 };
 
-use fs_extra::{dir::CopyOptions, file::read_to_string};
+use chrono::Local;
 
-use crate::plugin::{Plugin, PluginType};
+use crate::{
+    archive::{self, ArchiveFormat},
+    atomic, builder,
+    checksum::{self, sha256_hex},
+    collection::Collection,
+    credentials::Credentials,
+    ini::Document,
+    installed::{InstalledCache, InstalledPlugin},
+    manifest::{self, Manifest},
+    mirror,
+    packages::{self, PackageDatabase},
+    platform,
+    plugin::{is_valid_ref, split_qualified_name, split_ref, Plugin, PluginType, AD_HOC_REPO},
+    remote::{self, is_remote, FetchResult},
+    repo::{self, Freshness, Repo, OFFICIAL_REPO_NAME},
+    repo_state::RepoStateCache,
+    requirements::{RequirementPolicy, Requirements, System},
+    trust::{self, parse_date, TrustStore, TrustedKey, SIGNATURE_EXTENSION},
+    vcs::{self, BuiltinGit, Change, DirtyPolicy, FallbackGit, RemoteRef, Vcs},
+    watch,
+};
+
+/// Copies a folder into a fresh staging folder, keeping links and
+/// permissions.
+fn stage_copy(path: &str, staging_path: &str) -> Result<(), String> {
+    if Path::new(staging_path).exists() {
+        fs::remove_dir_all(staging_path).map_err(|e| e.to_string())?;
+    }
+    fs::create_dir_all(staging_path).map_err(|e| e.to_string())?;
+    mirror::mirror_tree(Path::new(path), Path::new(staging_path), &[])?;
+    Ok(())
+}
+
+/// Warns about a repo or collection that is past its `valid-until`, or whose
+/// timestamps cannot be read.
+fn warn_if_outdated(kind: &str, name: &str, freshness: &Freshness) {
+    if freshness.is_stale() {
+        println!(
+            "Warning! {} {} has invalid timestamps! Please run 'rpi-mesh-plugin-manager update'!",
+            kind, name
+        );
+    } else if let Some(valid_until) = freshness.get_valid_until() {
+        if freshness.is_expired() {
+            println!(
+                "Warning! {} {} expired on {}! Please run 'rpi-mesh-plugin-manager update'!",
+                kind, name, valid_until
+            );
+        }
+    }
+}
+
+const LEGACY_SOURCE_FOLDER_LOCATION: &str = "/etc/rpi-mesh-plugin-manager/sources";
+
+/// Prefix of the repo state entries of remote collections, repo names cannot
+/// contain a `/`.
+const COLLECTION_STATE_PREFIX: &str = "collection/";
 
 pub struct PluginManager {
     plugins: HashMap<String, Plugin>,
+    shadowed: HashMap<String, Vec<Plugin>>,
+    collections: HashMap<String, Collection>,
+    repos: Vec<Repo>,
     config_location: String,
     official_repo_location: String,
     repo_folder_location: String,
     installed_cache_location: String,
     plugin_folder_location: String,
+    collection_cache_location: String,
+    repo_state_location: String,
+    trust_store_location: String,
+    source_folder_location: String,
+    backup_folder_location: String,
+    credentials_location: String,
+    log_folder_location: String,
+    untrusted_repos: Vec<String>,
+    git_backend: String,
+    credentials: Credentials,
+    vcs: Box<dyn Vcs>,
+    dirty_policy: DirtyPolicy,
+    block_untracked: bool,
+    platform: String,
+    root_prefix: String,
+    requirement_policy: RequirementPolicy,
+    package_command: Option<String>,
 }
 
 impl PluginManager {
@@ -26,15 +100,31 @@ impl PluginManager {
         let repo_folder_location = String::from("/etc/rpi-mesh-plugin-manager/repos");
         let installed_cache_location = String::from("/etc/rpi-mesh-plugin-manager/.installed");
         let plugin_folder_location = String::from("/var/www/html/plugins");
+        let collection_cache_location = String::from("/etc/rpi-mesh-plugin-manager/collections");
+        let repo_state_location = String::from("/etc/rpi-mesh-plugin-manager/.repo_state");
+        let trust_store_location = String::from("/etc/rpi-mesh-plugin-manager/trusted.keys");
+        let source_folder_location = String::from("/var/lib/rpi-mesh-plugin-manager/sources");
+        let backup_folder_location = String::from("/var/lib/rpi-mesh-plugin-manager/backups");
+        let credentials_location = String::from("/etc/rpi-mesh-plugin-manager/credentials");
+        let log_folder_location = String::from("/var/log/rpi-mesh-plugin-manager");
 
         // let config_location = String::from("config.conf");
         // let official_repo_location = String::from("plugins.repo");
         // let repo_folder_location = String::from("repos");
         // let installed_cache_location = String::from(".installed");
         // let plugin_folder_location = String::from("plugins");
+        // let collection_cache_location = String::from("collections");
+        // let repo_state_location = String::from(".repo_state");
+        // let trust_store_location = String::from("trusted.keys");
+        // let source_folder_location = String::from("sources");
+        // let backup_folder_location = String::from("backups");
+        // let credentials_location = String::from("credentials");
+        // let log_folder_location = String::from("logs");
 
+        // The official repo is not signed yet, so a new config allows it
+        // without a signature.
         if !Path::new(&config_location).is_file() {
-            if let Err(e) = fs::File::create(&config_location) {
+            if let Err(e) = fs::write(&config_location, "untrusted_repos: official\n") {
                 println!(
                     "Error! Config file is missing and, it cannot be created! {}",
                     e
@@ -78,44 +168,172 @@ impl PluginManager {
             }
         }
 
+        if !Path::new(&collection_cache_location).is_dir() {
+            if let Err(e) = fs::create_dir(&collection_cache_location) {
+                println!(
+                    "Error! Collection cache folder missing and, it cannot be created! {}",
+                    e
+                )
+            }
+        }
+
+        // Clones used to be kept next to the config, they are moved once so
+        // installed plugins keep their clone.
+        if !Path::new(&source_folder_location).is_dir()
+            && Path::new(LEGACY_SOURCE_FOLDER_LOCATION).is_dir()
+        {
+            if let Err(e) = Path::new(&source_folder_location)
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::rename(LEGACY_SOURCE_FOLDER_LOCATION, &source_folder_location))
+            {
+                println!(
+                    "Warning! Cannot move {} to {}! Error: {}",
+                    LEGACY_SOURCE_FOLDER_LOCATION, source_folder_location, e
+                )
+            }
+        }
+
+        if !Path::new(&source_folder_location).is_dir() {
+            if let Err(e) = fs::create_dir_all(&source_folder_location) {
+                println!(
+                    "Error! Source folder missing and, it cannot be created! {}",
+                    e
+                )
+            }
+        }
+
         let mut plugin_manager = PluginManager {
             plugins: HashMap::new(),
+            shadowed: HashMap::new(),
+            collections: HashMap::new(),
+            repos: Vec::new(),
             config_location,
             repo_folder_location,
             official_repo_location,
             installed_cache_location,
             plugin_folder_location,
+            collection_cache_location,
+            repo_state_location,
+            trust_store_location,
+            source_folder_location,
+            backup_folder_location,
+            credentials_location,
+            log_folder_location,
+            untrusted_repos: Vec::new(),
+            git_backend: String::from("builtin"),
+            credentials: Credentials::default(),
+            vcs: Box::new(BuiltinGit::new(Credentials::default())),
+            dirty_policy: DirtyPolicy::Abort,
+            block_untracked: false,
+            platform: String::new(),
+            root_prefix: String::new(),
+            requirement_policy: RequirementPolicy::Refuse,
+            package_command: None,
         };
 
         plugin_manager.read_config();
+        if plugin_manager.platform.is_empty() {
+            plugin_manager.platform = platform::detect();
+        }
+        plugin_manager.load_credentials();
         plugin_manager
     }
 
+    /// Loads the credentials and sets up the git backend with them.
+    fn load_credentials(&mut self) {
+        self.credentials = Credentials::load(&self.credentials_location);
+        self.vcs = match vcs::backend(&self.git_backend, self.credentials.clone()) {
+            Some(vcs) => vcs,
+            None => {
+                println!(
+                    "Warning! Unknown git backend {}! Using the builtin one!",
+                    self.git_backend
+                );
+                Box::new(FallbackGit::new(self.credentials.clone()))
+            }
+        };
+    }
+
     pub fn read_config(&mut self) {
         match fs::read_to_string(&self.config_location) {
             Ok(i) => {
                 for line in i.lines() {
                     let data: Vec<&str> = line.split(':').map(|x| x.trim()).collect();
                     match data[0] {
-                        "installed_cache_location" => {
-                            if Path::new(&data[1].to_string()).is_file() {
-                                self.installed_cache_location = data[1].to_string()
-                            }
+                        "installed_cache_location" if Path::new(&data[1].to_string()).is_file() => {
+                            self.installed_cache_location = data[1].to_string();
                         }
-                        "official_repo_location" => {
-                            if Path::new(&data[1].to_string()).is_file() {
-                                self.official_repo_location = data[1].to_string();
-                            }
+                        "official_repo_location" if Path::new(&data[1].to_string()).is_file() => {
+                            self.official_repo_location = data[1].to_string();
                         }
-                        "repo_folder_location" => {
-                            if Path::new(&data[1].to_string()).is_file() {
-                                self.repo_folder_location = data[1].to_string();
-                            }
+                        "repo_folder_location" if Path::new(&data[1].to_string()).is_file() => {
+                            self.repo_folder_location = data[1].to_string();
                         }
-                        "plugin_folder_location" => {
-                            if Path::new(&data[1].to_string()).is_dir() {
-                                self.plugin_folder_location = data[1].to_string();
-                            }
+                        "plugin_folder_location" if Path::new(&data[1].to_string()).is_dir() => {
+                            self.plugin_folder_location = data[1].to_string();
+                        }
+                        "collection_cache_location" if Path::new(&data[1].to_string()).is_dir() => {
+                            self.collection_cache_location = data[1].to_string();
+                        }
+                        "repo_state_location" => {
+                            self.repo_state_location = data[1].to_string();
+                        }
+                        "trust_store_location" => {
+                            self.trust_store_location = data[1].to_string();
+                        }
+                        "source_folder_location" if Path::new(&data[1].to_string()).is_dir() => {
+                            self.source_folder_location = data[1].to_string();
+                        }
+                        "backup_folder_location" => {
+                            self.backup_folder_location = data[1].to_string();
+                        }
+                        "dirty_policy" => match DirtyPolicy::parse(data[1]) {
+                            Some(policy) => self.dirty_policy = policy,
+                            None => println!(
+                                "Warning! Unknown dirty policy {}! Aborting upgrades of changed plugins!",
+                                data[1]
+                            ),
+                        },
+                        "untracked_files" => match data[1] {
+                            "block" => self.block_untracked = true,
+                            "ignore" => self.block_untracked = false,
+                            _ => println!(
+                                "Warning! Unknown untracked files setting {}! Ignoring untracked files!",
+                                data[1]
+                            ),
+                        },
+                        "git_backend" => {
+                            self.git_backend = data[1].to_string();
+                        }
+                        "root_prefix" => {
+                            self.root_prefix = data[1].to_string();
+                        }
+                        "requirement_policy" => match RequirementPolicy::parse(data[1]) {
+                            Some(policy) => self.requirement_policy = policy,
+                            None => println!(
+                                "Warning! Unknown requirement policy {}! Refusing plugins with unmet requirements!",
+                                data[1]
+                            ),
+                        },
+                        "package_command" if !data[1].is_empty() => {
+                            self.package_command = Some(data[1].to_string());
+                        }
+                        "platform" => {
+                            self.platform = data[1].to_string();
+                        }
+                        "credentials_location" => {
+                            self.credentials_location = data[1].to_string();
+                        }
+                        "log_folder_location" => {
+                            self.log_folder_location = data[1].to_string();
+                        }
+                        "untrusted_repos" => {
+                            self.untrusted_repos = data[1]
+                                .split(',')
+                                .map(|x| x.trim().to_string())
+                                .filter(|x| !x.is_empty())
+                                .collect();
                         }
                         _ => {}
                     }
@@ -130,439 +348,2204 @@ impl PluginManager {
     }
 
     pub fn cache_repos(&mut self) {
-        self.read_repos(self.official_repo_location.clone());
+        self.repos = self.get_repos();
 
-        match read_dir(&self.repo_folder_location) {
-            Ok(repos) => {
-                for repo in repos {
-                    match repo {
-                        Ok(name) => {
-                            self.read_repos(name.path().display().to_string());
-                        }
-                        Err(e) => println!("Error while gettin repo! Error: {}", &e),
-                    }
+        for repo in self.repos.clone() {
+            if !repo.is_enabled() {
+                continue;
+            }
+
+            if self.is_trusted(&repo) {
+                if let Err(e) = self.verify_file(&repo.get_location(), &repo) {
+                    println!(
+                        "Warning! Skipping repo {}, it is not signed by a trusted key! Error: {}",
+                        repo.get_name(),
+                        e
+                    );
+                    continue;
                 }
             }
-            Err(e) => println!("Error while gettin repos folder location! Error: {}", &e),
-        };
+
+            warn_if_outdated("Repo", &repo.get_name(), repo.get_freshness());
+
+            if let Err(e) = self.read_repos(repo.get_location(), &repo, &mut Vec::new()) {
+                println!("Error while reading repo {}! Error: {}", repo.get_name(), e);
+            }
+        }
     }
 
-    fn load_into_plugins(&mut self, plugins_string: String) {
-        let mut lines = plugins_string.lines();
-        let mut line = lines.nth(0);
-        while line != None {
-            if let Some(first_char) = line.unwrap().chars().nth(0) {
-                if first_char == '[' {
-                    let name = line.unwrap().replace('[', "").replace(']', "");
-                    let mut enabled: bool = false;
-                    let mut plugin_type: PluginType = PluginType::Repo;
-                    let mut location: String = String::new();
-
-                    line = lines.next();
-                    while line != None
-                        && line.unwrap().chars().nth(0) != None
-                        && line.unwrap().chars().nth(0).unwrap() != '['
-                    {
-                        let data: Vec<&str> = line.unwrap().split('=').map(|x| x.trim()).collect();
+    /// Repos listed in `untrusted_repos` in the config are used without
+    /// checking their signature.
+    fn is_trusted(&self, repo: &Repo) -> bool {
+        !self.untrusted_repos.contains(&repo.get_name())
+    }
 
-                        match data[0] {
-                            "enabled" => {
-                                if data[1] == "true" {
-                                    enabled = true;
-                                }
-                            }
-                            "type" => match data[1] {
-                                "repo" => plugin_type = PluginType::Repo,
-                                "local" => plugin_type = PluginType::Local,
-                                "collection" => plugin_type = PluginType::Collection,
-                                _ => {}
-                            },
-                            "location" => {
-                                if plugin_type == PluginType::Collection {
-                                    self.read_repos(data[1].to_string());
-                                }
-                                location = data[1].to_string();
-                            }
-                            _ => {}
-                        }
+    fn load_trust_store(&self) -> TrustStore {
+        TrustStore::load(&self.trust_store_location)
+    }
 
-                        line = lines.next();
-                    }
+    /// Verifies the local copy of a repo or cached collection against the
+    /// detached signature stored next to it.
+    fn verify_file(&self, location: &str, repo: &Repo) -> Result<TrustedKey, String> {
+        let content = fs::read(location).map_err(|e| e.to_string())?;
+        let signature = fs::read_to_string(atomic::sibling_path(location, SIGNATURE_EXTENSION))
+            .map_err(|_| String::from("Signature is missing"))?;
 
-                    if plugin_type != PluginType::Collection && enabled {
-                        self.plugins.insert(
-                            name.clone(),
-                            Plugin::new(name, enabled, plugin_type, location),
+        self.load_trust_store()
+            .verify(&content, &signature, &repo.get_name())
+    }
+
+    /// A repo can announce the key replacing the one it is signed with by
+    /// `successor_key` in its header, and retire the current key with
+    /// `current_key_expires`. The header is covered by the signature, so the
+    /// announcement is signed by the current key. A repo can only hand itself
+    /// over, so the successor may sign the announcing repo, and a key signing
+    /// several repos is handed over as each of them announces the successor.
+    fn rotate_keys(&self, repo: &Repo, signing_key: &TrustedKey, content: &str) {
+        let document = Document::parse(content);
+        let header = document.get_header();
+        let mut trust_store = self.load_trust_store();
+        let mut changed = false;
+
+        if let Some(successor_key) = header.get("successor_key") {
+            match TrustedKey::new(successor_key, vec![repo.get_name()], None) {
+                Ok(key) => {
+                    let key = match trust_store.get(&key.get_id()) {
+                        Some(trusted_key) if trusted_key.is_allowed_for(&repo.get_name()) => None,
+                        Some(trusted_key) => {
+                            let mut trusted_key = trusted_key.clone();
+                            trusted_key.add_repo(repo.get_name());
+                            Some(trusted_key)
+                        }
+                        None => Some(key),
+                    };
+                    if let Some(key) = key {
+                        println!(
+                            "Trusting successor key {} for repo {}!",
+                            key.get_id(),
+                            repo.get_name()
                         );
+                        trust_store.insert(key);
+                        changed = true;
                     }
                 }
-            };
-            line = lines.next();
+                Err(e) => println!(
+                    "Warning! Invalid successor key in repo {}! Error: {}",
+                    repo.get_name(),
+                    e
+                ),
+            }
         }
-    }
 
-    fn read_repos(&mut self, location: String) {
-        match fs::read_to_string(location) {
-            Ok(i) => {
-                self.load_into_plugins(i);
+        if let Some(expires) = header.get("current_key_expires") {
+            match parse_date(&expires) {
+                Ok(expires) if signing_key.get_expires().is_none_or(|x| expires < x) => {
+                    println!(
+                        "Key {} expires on {} as announced by repo {}!",
+                        signing_key.get_id(),
+                        expires,
+                        repo.get_name()
+                    );
+                    let mut key = signing_key.clone();
+                    key.set_expires(Some(expires));
+                    trust_store.insert(key);
+                    changed = true;
+                }
+                Ok(_) => {}
+                Err(e) => println!(
+                    "Warning! Invalid key expiry in repo {}! Error: {}",
+                    repo.get_name(),
+                    e
+                ),
             }
-            Err(e) => {
-                println!("Error: {}! Exiting plugin manager!", e);
-                exit(1);
+        }
+
+        if changed {
+            if let Err(e) = trust_store.save() {
+                println!("Cannot write trust store! Error: {}", e);
             }
         }
     }
 
-    pub fn install(&self, args: std::env::Args) {
-        if self.plugins.len() == 0 {
-            println!("No plugins found in repos! Please run 'rpi-mesh-plugin-manager update'!");
-            exit(1);
+    /// Fetches the detached signature published next to the url and verifies
+    /// the content with it, returning the signature and the key that made it.
+    fn fetch_signature(
+        &self,
+        url: &str,
+        content: &str,
+        repo: &Repo,
+    ) -> Result<(String, TrustedKey), String> {
+        let signature_url = format!("{}.{}", url, SIGNATURE_EXTENSION);
+        let signature = remote::fetch(&signature_url, self.credentials.get(&signature_url))
+            .map_err(|e| format!("Cannot fetch signature! {}", e))?;
+        let key =
+            self.load_trust_store()
+                .verify(content.as_bytes(), &signature, &repo.get_name())?;
+
+        Ok((signature, key))
+    }
+
+    fn get_repos(&self) -> Vec<Repo> {
+        let mut repos = Vec::new();
+
+        match Repo::from_file(
+            OFFICIAL_REPO_NAME.to_string(),
+            self.official_repo_location.clone(),
+        ) {
+            Ok(repo) => repos.push(repo),
+            Err(e) => println!("Error while reading official repo! Error: {}", e),
         }
-        let plugins_to_install: Vec<String> = args.skip(2).collect();
-        for plugin in plugins_to_install {
-            if self.plugins.contains_key(&plugin) {
-                if let Some(plugint_to_be_installed) = self.plugins.get(&plugin) {
-                    print!("Installing plugin {}...", plugin);
-                    match plugint_to_be_installed.get_plugin_type() {
-                        PluginType::Local => self.install_local_plugin(plugint_to_be_installed),
-                        PluginType::Repo => self.install_git_plugin(plugint_to_be_installed),
-                        _ => {
-                            println!("Wrong plugin type! Skipping {}", plugin)
-                        }
+
+        let mut paths = Vec::new();
+        match read_dir(&self.repo_folder_location) {
+            Ok(entries) => {
+                for entry in entries {
+                    match entry {
+                        Ok(entry) if entry.file_name().to_string_lossy().starts_with('.') => {}
+                        Ok(entry) => paths.push(entry.path()),
+                        Err(e) => println!("Error while gettin repo! Error: {}", &e),
                     }
-                } else {
-                    println!("Error getting plugin!");
                 }
-            } else {
-                println!("No plugin named {}! Skipping!", plugin);
+            }
+            Err(e) => println!("Error while gettin repos folder location! Error: {}", &e),
+        };
+
+        // Repo names come from the file stem, so `team.repo` and `team.list`
+        // would both be `team`. The first file in name order wins.
+        paths.sort();
+        let mut seen: HashMap<String, String> = HashMap::from([(
+            OFFICIAL_REPO_NAME.to_string(),
+            self.official_repo_location.clone(),
+        )]);
+        for path in paths {
+            let name = Repo::name_from_path(&path);
+            if let Some(location) = seen.get(&name) {
+                println!(
+                    "Error! Skipping repo {}, repo {} is already defined by {}!",
+                    path.display(),
+                    name,
+                    location
+                );
+                continue;
+            }
+            seen.insert(name.clone(), path.display().to_string());
+
+            match Repo::from_file(name, path.display().to_string()) {
+                Ok(repo) => repos.push(repo),
+                Err(e) => println!("Error while reading repo {}! Error: {}", path.display(), e),
             }
         }
+
+        repos.sort_by(|a, b| a.precedence(b));
+        repos
     }
 
-    fn run_setup(&self, plugin_location: String) -> bool {
-        if let Ok(status) =
-            Command::new(format!("{}/setup_scripts/setup.sh", plugin_location)).status()
+    fn get_repo(&self, name: &str) -> Option<&Repo> {
+        self.repos.iter().find(|x| x.get_name() == name)
+    }
+
+    /// Loads the plugins of a repo or collection file and returns the
+    /// qualified names of every enabled plugin in it, including the ones of
+    /// nested collections.
+    fn load_into_plugins(
+        &mut self,
+        plugins_string: String,
+        repo: &Repo,
+        include_chain: &mut Vec<String>,
+    ) -> Vec<String> {
+        let document = match Document::parse_strict(&plugins_string)
+            .and_then(|x| repo::validate_plugins(&x).map(|_| x))
         {
-            if let Some(code) = status.code() {
-                if code == 0 {
-                    return true;
-                } else {
-                    return false;
-                }
+            Ok(document) => document,
+            Err(e) => {
+                println!(
+                    "Error! Skipping invalid plugins in repo {}! Error: {}",
+                    repo.get_name(),
+                    e
+                );
+                return Vec::new();
             }
-            println!("Error getting status code from setup script!");
-            return false;
-        }
-        println!("Error running setup script!");
-        false
-    }
+        };
+        let mut members = Vec::new();
 
-    fn install_local_plugin(&self, plugin: &Plugin) {
-        if Path::is_dir(Path::new(&plugin.get_location())) {
-            let plugin_path = format!("{}/{}", &self.plugin_folder_location, &plugin.get_name());
+        for section in document.get_sections() {
+            let plugin = Plugin::from_section(section, repo.get_name());
 
-            if Path::is_dir(Path::new(&plugin_path)) {
-                println!("Plugin {} is already installed!", plugin.get_name());
-            } else {
-                if let Err(e) = fs_extra::dir::copy(
-                    plugin.get_location(),
-                    &self.plugin_folder_location,
-                    &CopyOptions::new(),
-                ) {
-                    println!(
-                        "Error while installing {}! Skipping plugin! Error: {}",
+            if plugin.get_plugin_type() == PluginType::Collection {
+                if !plugin.is_enabled() {
+                    continue;
+                }
+                match self.read_repos(plugin.get_location(), repo, include_chain) {
+                    Ok(collection_members) => {
+                        members.extend(collection_members.clone());
+                        self.collections
+                            .entry(plugin.get_name())
+                            .or_insert_with(|| Collection::new(plugin, collection_members));
+                    }
+                    Err(e) => println!(
+                        "Warning! Skipping collection {} in repo {}! Error: {}",
                         plugin.get_name(),
+                        repo.get_name(),
                         e
-                    )
-                } else {
-                    if Path::new(&format!("{}/setup_scripts/setup.sh", plugin_path)).is_file() {
-                        if !self.run_setup(plugin_path) {
-                            print!("Error while running setup script! Pluginin is copied to plugin forder! Please manually install {} plugin if installation is needed!", plugin.get_name());
-                        }
-                    }
-                    self.add_to_installed_cache(plugin.get_name(), true);
-                    println!("OK!")
+                    ),
                 }
+            } else if plugin.is_enabled() {
+                members.push(format!("{}/{}", plugin.get_repo(), plugin.get_name()));
+                self.add_plugin(plugin);
             }
-        } else {
-            println!("Cannot find plugin: {}! Skipping!", plugin.get_name());
         }
-    }
 
-    fn install_git_plugin(&self, plugin: &Plugin) {
-        let status = Command::new("git")
-            .arg("clone")
-            .arg(plugin.get_location())
-            .arg(format!(
-                "{}/{}",
-                &self.plugin_folder_location,
-                plugin.get_name()
-            ))
-            .status()
-            .expect("Cannot execute git command! Check if it is installed correctly!");
-
-        if let Some(code) = status.code() {
-            if code == 0 {
-                let plugin_path =
-                    format!("{}/{}", &self.plugin_folder_location, &plugin.get_name());
-                if Path::new(&format!("{}/setup_scripts/setup.sh", plugin_path)).is_file() {
-                    if !self.run_setup(plugin_path) {
-                        print!("Error while running setup script! Pluginin is copied to plugin forder! Please manually install {} plugin if installation is needed!", plugin.get_name());
-                    }
-                }
-                self.add_to_installed_cache(plugin.get_name(), true);
-                println!("OK!")
-            } else {
-                println!(
-                    "Git error code: {}! Skipping plugin {}!",
-                    code,
-                    plugin.get_name()
-                );
-            }
-        }
+        members
     }
 
-    pub fn upgrade(&self, args: std::env::Args) {
-        if self.plugins.len() == 0 {
-            println!("No plugins found in repos! Please run 'rpi-mesh-plugin-manager update'!");
-            exit(1);
-        }
-        if args.len() < 3 {
-            self.upgrade_all(self.get_installed_plugins());
-        } else {
-            let plugins_to_upgrade: Vec<String> = args.skip(2).collect();
-            for plugin in plugins_to_upgrade {
-                if self.plugins.contains_key(&plugin) {
-                    if let Some(plugint_to_be_upgraded) = self.plugins.get(&plugin) {
-                        print!("Upgrading plugin {}...", plugin);
-                        match plugint_to_be_upgraded.get_plugin_type() {
-                            PluginType::Local => self.upgrade_local_plugin(plugint_to_be_upgraded),
-                            PluginType::Repo => self.upgrade_git_plugin(plugint_to_be_upgraded),
-                            _ => {
-                                println!("Wrong plugin type! Skipping {}", plugin)
-                            }
-                        }
-                    } else {
-                        println!("Error getting plugin!");
-                    }
-                }
+    /// Repos are read in precedence order, so the first repo to define a
+    /// plugin wins and later definitions are kept as shadowed entries.
+    fn add_plugin(&mut self, plugin: Plugin) {
+        match self.plugins.entry(plugin.get_name()) {
+            Entry::Occupied(_) => self
+                .shadowed
+                .entry(plugin.get_name())
+                .or_default()
+                .push(plugin),
+            Entry::Vacant(entry) => {
+                entry.insert(plugin);
             }
         }
     }
 
-    pub fn upgrade_local_plugin(&self, plugin: &Plugin) {
-        if Path::is_dir(Path::new(&plugin.get_location())) {
-            let plugin_path = format!("{}/{}", &self.plugin_folder_location, &plugin.get_name());
+    /// Reads a repo or collection file, remote collections are read from the
+    /// collection cache filled by `update`. The include chain holds the
+    /// identities of the files currently being read to detect cycles.
+    fn read_repos(
+        &mut self,
+        location: String,
+        repo: &Repo,
+        include_chain: &mut Vec<String>,
+    ) -> Result<Vec<String>, String> {
+        self.enter_collection(&location, include_chain)?;
+        let members = self
+            .read_collection(&location, repo)
+            .map(|content| self.load_into_plugins(content, repo, include_chain));
+        include_chain.pop();
+        members
+    }
 
-            if Path::is_dir(Path::new(&plugin_path)) && plugin.get_name() != "" {
-                let mut options = CopyOptions::new();
-                options.overwrite = true;
-                if let Err(e) = fs_extra::dir::copy(
-                    plugin.get_location(),
-                    &self.plugin_folder_location,
-                    &options,
-                ) {
-                    println!(
-                        "Error while upgrading {}! Skipping plugin! Error: {}",
-                        plugin.get_name(),
-                        e
-                    )
-                } else {
-                    println!("OK!");
-                }
-            } else {
-                println!(
-                    "Plugin {} is not installed so it cannot be upgraded!",
-                    plugin.get_name()
-                );
-            }
+    fn collection_identity(&self, location: &str) -> String {
+        if is_remote(location) {
+            location.trim_end_matches('/').to_string()
         } else {
-            println!("Cannot find plugin: {}! Skipping!", plugin.get_name());
+            fs::canonicalize(location)
+                .map(|x| x.display().to_string())
+                .unwrap_or_else(|_| location.to_string())
         }
     }
 
-    pub fn upgrade_git_plugin(&self, plugin: &Plugin) {
-        let status = Command::new("git")
-            .arg("-C")
-            .arg(format!(
-                "{}/{}",
-                &self.plugin_folder_location,
-                plugin.get_name()
-            ))
-            .arg("pull")
-            .status()
-            .expect("Cannot execute git command! Check if it is installed correctly!");
-
-        if let Some(code) = status.code() {
-            if code == 0 {
-                println!("OK!");
-            } else {
-                println!(
-                    "Git error code: {}! Skipping plugin {}!",
-                    code,
-                    plugin.get_name()
-                );
-            }
+    fn enter_collection(
+        &self,
+        location: &str,
+        include_chain: &mut Vec<String>,
+    ) -> Result<(), String> {
+        let identity = self.collection_identity(location);
+        if include_chain.contains(&identity) {
+            return Err(format!(
+                "Collection cycle detected: {} -> {}",
+                include_chain.join(" -> "),
+                identity
+            ));
         }
+        include_chain.push(identity);
+        Ok(())
     }
 
-    fn upgrade_all(&self, plugins: Vec<String>) {
-        for plugin in plugins {
-            if let Some(seclected_plugin) = self.plugins.get(&plugin) {
-                print!("Upgrading plugin {}...", plugin);
-                match seclected_plugin.get_plugin_type() {
-                    PluginType::Local => self.upgrade_local_plugin(seclected_plugin),
-                    PluginType::Repo => self.upgrade_git_plugin(seclected_plugin),
-                    _ => {
-                        println!("Wrong plugin type! Skipping {}", plugin)
-                    }
-                }
-            } else {
-                println!("Skipping {}! No such plugin!", plugin);
-            }
-        }
+    fn collection_cache_path(&self, url: &str) -> String {
+        format!(
+            "{}/{}",
+            self.collection_cache_location,
+            remote::cache_file_name(url)
+        )
     }
 
-    fn add_to_installed_cache(&self, plugin_name: String, append: bool) {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .append(append)
-            .open(&self.installed_cache_location)
-            .expect("Cannot open installed cache!");
+    fn read_collection(&self, location: &str, repo: &Repo) -> Result<String, String> {
+        if is_remote(location) {
+            let cache_path = self.collection_cache_path(location);
+            let content = fs::read_to_string(&cache_path).map_err(|_| {
+                format!(
+                    "Remote collection {} is not cached! Please run 'rpi-mesh-plugin-manager update'!",
+                    location
+                )
+            })?;
 
-        if let Err(e) = writeln!(file, "{}", plugin_name) {
-            println!("Cannot write to installed cache! Error: {}", e)
+            if self.is_trusted(repo) {
+                self.verify_file(&cache_path, repo).map_err(|e| {
+                    format!("Collection is not signed by a trusted key! Error: {}", e)
+                })?;
+            }
+            warn_if_outdated("Collection", location, &Freshness::of_local_copy(&content));
+            Ok(content)
+        } else {
+            let content = fs::read_to_string(location).map_err(|e| e.to_string())?;
+            if self.is_trusted(repo) {
+                self.verify_file(location, repo).map_err(|e| {
+                    format!("Collection is not signed by a trusted key! Error: {}", e)
+                })?;
+            }
+            Ok(content)
         }
     }
 
-    fn get_installed_plugins(&self) -> Vec<String> {
-        read_to_string(&self.installed_cache_location)
-            .expect("Cannot read installed cache!")
-            .split("\n")
-            .map(|x| x.to_string())
-            .collect::<Vec<String>>()
-            .clone()
+    pub fn install(&self, args: std::env::Args) {
+        self.install_all(args.skip(2).collect());
     }
 
-    pub fn uninstall(&self, args: std::env::Args) {
-        if self.plugins.len() == 0 {
+    /// Installs plugins, collections and ad-hoc plugins from paths or urls.
+    /// The system packages of every installable plugin are installed first.
+    fn install_all(&self, plugins_to_install: Vec<String>) {
+        if self.plugins.is_empty()
+            && plugins_to_install
+                .iter()
+                .any(|x| Plugin::ad_hoc(x).is_none())
+        {
             println!("No plugins found in repos! Please run 'rpi-mesh-plugin-manager update'!");
             exit(1);
         }
-        let plugins_to_delete: Vec<String> = args.skip(2).collect();
-        for plugin in plugins_to_delete {
-            if self.plugins.contains_key(&plugin) {
-                if let Some(plugint_to_be_uninstalled) = self.plugins.get(&plugin) {
-                    let plugin_path = format!(
-                        "{}/{}",
-                        &self.plugin_folder_location,
-                        &plugint_to_be_uninstalled.get_name()
-                    );
-
-                    if Path::new(&plugin_path).is_dir() {
-                        print!("Uninstalling plugin {}...", plugin);
-
-                        if let Err(e) = fs::remove_dir_all(plugin_path) {
-                            println!("Error while uninstalling {}! Error: {}", plugin, e);
-                        } else {
-                            println!("Ok!");
-                            let mut installed_plugins = self.get_installed_plugins();
-                            let remove_index = installed_plugins
-                                .iter()
-                                .position(|x| x == &plugin && x != "")
-                                .unwrap();
-                            installed_plugins.remove(remove_index);
-                            let mut content = String::new();
-
-                            for installed_plugin in installed_plugins {
-                                let tmp_plugin: String = installed_plugin;
-                                content.push_str(tmp_plugin.as_str());
-                            }
+        let mut plan: Vec<(String, &Plugin, Option<String>, Option<String>)> = Vec::new();
+        let mut ad_hoc_plugins = Vec::new();
+        for argument in plugins_to_install {
+            if let Some(ad_hoc_plugin) = Plugin::ad_hoc(&argument) {
+                ad_hoc_plugins.push(ad_hoc_plugin);
+                continue;
+            }
+            let (plugin, git_ref) = split_ref(&argument);
+            if git_ref.is_some_and(|x| !is_valid_ref(x)) {
+                println!("Invalid ref in {}! Skipping!", argument);
+                continue;
+            }
 
-                            if let Err(e) = fs::write(&self.installed_cache_location, content) {
-                                println!(
-                                    "Error while writing to installed plugin cache! Error: {}",
-                                    e
-                                );
-                            }
+            if let Some(plugint_to_be_installed) = self.resolve_plugin(plugin) {
+                plan.push((
+                    plugin.to_string(),
+                    plugint_to_be_installed,
+                    git_ref.map(String::from),
+                    None,
+                ));
+            } else if let Some(collection) = self.collections.get(plugin) {
+                if git_ref.is_some() {
+                    println!("Collection {} cannot be pinned to a ref! Skipping!", plugin);
+                    continue;
+                }
+                println!("Installing collection {}...", plugin);
+                for member in collection.get_members() {
+                    match self.resolve_plugin(&member) {
+                        Some(member_plugin) => {
+                            plan.push((member, member_plugin, None, Some(plugin.to_string())))
                         }
-                    } else {
-                        println!("Plugin {} is not installed!", plugin);
+                        None => println!("No plugin named {}! Skipping!", member),
                     }
-                } else {
-                    println!("Error getting plugin!");
                 }
+            } else {
+                println!("No plugin named {}! Skipping!", plugin);
             }
         }
-    }
-
-    pub fn update(&mut self) {
-        print!("Updating official repo...");
-        self.update_repo(&self.official_repo_location);
 
-        match read_dir(&self.repo_folder_location) {
-            Ok(repos) => {
-                for repo in repos {
-                    match repo {
-                        Ok(name) => {
-                            print!("Updating {} repo...", name.file_name().to_str().unwrap());
-                            self.update_repo(&name.path().display().to_string());
-                        }
-                        Err(e) => println!("Error while gettin repo! Error: {}", &e),
+        let plan: Vec<(String, Plugin, Option<String>, Option<String>)> = plan
+            .into_iter()
+            .filter_map(|(name, plugin, git_ref, collection)| {
+                match self.select_installable(plugin) {
+                    Ok(plugin) => Some((name, plugin, git_ref, collection)),
+                    Err(e) => {
+                        println!("{} Skipping plugin {}!", e, name);
+                        None
                     }
                 }
-            }
-            Err(e) => println!("Error while gettin repos folder location! Error: {}", &e),
-        };
+            })
+            .collect();
+        self.install_missing_packages(
+            plan.iter()
+                .map(|(_, plugin, _, _)| plugin)
+                .filter(|x| !self.is_installed(&x.get_name())),
+        );
+        for (name, plugin, git_ref, collection) in plan {
+            let was_installed = self
+                .load_installed_cache()
+                .get(&plugin.get_name())
+                .is_some();
+            self.install_plugin(&name, &plugin, git_ref);
+            self.record_install_reason(&plugin.get_name(), collection, was_installed);
+        }
+        for ad_hoc_plugin in ad_hoc_plugins {
+            self.install_ad_hoc_plugin(ad_hoc_plugin);
+        }
     }
 
-    pub fn update_repo(&self, location: &String) {
-        match self.get_remote_from_config(&location) {
-            Some(remote) => {
-                if let Ok(resp) = reqwest::blocking::get(remote) {
-                    if let Ok(text) = resp.text() {
-                        let mut file = OpenOptions::new()
-                            .write(true)
-                            .append(false)
-                            .open(location)
-                            .expect("Cannot open repo for upgrading!");
-
-                        if let Err(e) = writeln!(file, "{}", text) {
-                            println!("Cannot write to repo! Error: {}", e)
-                        }
-                        println!("OK!")
-                    } else {
-                        println!("Error while getting text from remote repo!");
-                    }
-                } else {
-                    println!("Error while getting remote repoes!");
+    /// Records why a plugin is installed. Members of a collection remember
+    /// the collection, and a plugin first installed through a collection is
+    /// removed with it unless it is installed by name later.
+    fn record_install_reason(
+        &self,
+        plugin_name: &str,
+        collection: Option<String>,
+        was_installed: bool,
+    ) {
+        let mut installed_cache = self.load_installed_cache();
+        let Some(mut installed_plugin) = installed_cache.get(plugin_name).cloned() else {
+            return;
+        };
+
+        match collection {
+            Some(collection) => {
+                let mut collections = installed_plugin.get_collections();
+                if !collections.contains(&collection) {
+                    collections.push(collection);
+                }
+                installed_plugin.set_collections(collections);
+                if !was_installed {
+                    installed_plugin.set_auto(true);
                 }
             }
-            None => println!("Skipping! No remote defined in repo!"),
+            None => installed_plugin.set_auto(false),
         }
-    }
 
-    fn get_remote_from_config(&self, location: &String) -> Option<String> {
-        let mut remote_url: Option<String> = None;
+        installed_cache.insert(installed_plugin);
+        if let Err(e) = installed_cache.save() {
+            println!("Cannot write to installed cache! Error: {}", e)
+        }
+    }
 
-        match fs::read_to_string(location) {
-            Ok(i) => {
-                if i.contains("remote") {
-                    for line in i.lines() {
-                        let data: Vec<&str> = line.split('=').map(|x| x.trim()).collect();
-                        if let "remote" = data[0] {
-                            remote_url = Some(data[1].to_string());
-                            break;
+    /// Installs a plugin from a path or url. Archives and git repositories
+    /// are fetched into a hidden folder first, as the name of the plugin
+    /// comes from its manifest.
+    fn install_ad_hoc_plugin(&self, mut plugin: Plugin) {
+        print!("Installing {}...", plugin.get_location());
+        let staged = match plugin.get_plugin_type() {
+            PluginType::Local => Ok((None, plugin.get_location(), None)),
+            PluginType::Archive => {
+                self.stage_archive_plugin(&plugin)
+                    .map(|(staging_path, root_path, sha256)| {
+                        (Some(staging_path), root_path, Some(sha256))
+                    })
+            }
+            _ => self
+                .stage_git_plugin(&plugin)
+                .map(|staging_path| (Some(staging_path.clone()), staging_path, None)),
+        };
+        let (staging_path, root_path, sha256) = match staged {
+            Ok(staged) => staged,
+            Err(e) => {
+                println!("{} Skipping {}!", e, plugin.get_location());
+                return;
+            }
+        };
+        let remove_staging = || {
+            if let Some(staging_path) = &staging_path {
+                let _ = fs::remove_dir_all(staging_path);
+            }
+        };
+
+        if let Err(e) = self.name_ad_hoc_plugin(&mut plugin, &root_path) {
+            remove_staging();
+            println!("{} Skipping {}!", e, plugin.get_location());
+            return;
+        }
+
+        let plugin_path = format!("{}/{}", &self.plugin_folder_location, &plugin.get_name());
+        if Path::new(&plugin_path).exists() {
+            remove_staging();
+            println!("Plugin {} is already installed!", plugin.get_name());
+            return;
+        }
+        // Once named, the plugin goes through the checks of plugins from repos.
+        let plugin = match self.select_installable(&plugin) {
+            Ok(plugin) => plugin,
+            Err(e) => {
+                remove_staging();
+                println!("{} Skipping plugin {}!", e, plugin.get_name());
+                return;
+            }
+        };
+        self.install_missing_packages(std::iter::once(&plugin));
+        if !self.check_packages(&plugin) {
+            remove_staging();
+            return;
+        }
+        if plugin.get_plugin_type() == PluginType::Local {
+            self.install_local_plugin(&plugin);
+            return;
+        }
+        if let Err(e) = self
+            .check_manifest_requirements(&plugin.get_name(), &root_path)
+            .and_then(|_| self.build_plugin(&plugin.get_name(), &root_path))
+        {
+            remove_staging();
+            println!("{} Skipping {}!", e, plugin.get_location());
+            return;
+        }
+
+        let moved = fs::rename(&root_path, &plugin_path);
+        remove_staging();
+        if let Err(e) = moved {
+            println!(
+                "Error while installing {}! Skipping plugin! Error: {}",
+                plugin.get_name(),
+                e
+            );
+            return;
+        }
+        self.setup_plugin(&plugin.get_name(), &plugin_path);
+        match sha256 {
+            Some(sha256) => self.add_archive_to_installed_cache(&plugin, sha256),
+            None => self.add_to_installed_cache(&plugin, None),
+        }
+        println!("OK!")
+    }
+
+    /// Names an ad-hoc plugin after its manifest, it keeps the name from its
+    /// location if the manifest has none. The packages it needs come from the
+    /// manifest too.
+    fn name_ad_hoc_plugin(&self, plugin: &mut Plugin, root_path: &str) -> Result<(), String> {
+        let manifest = Manifest::load(Path::new(root_path))?;
+        if let Some(name) = manifest.get_name() {
+            plugin.set_name(name);
+        }
+        plugin.set_packages(manifest.get_packages());
+        if !manifest::is_valid_name(&plugin.get_name()) {
+            return Err(format!(
+                "Cannot derive a plugin name, please set a name in {}!",
+                manifest::MANIFEST_FILE
+            ));
+        }
+        Ok(())
+    }
+
+    /// Installs local plugins as links to their folder, so changes show up
+    /// without upgrading. The setup script runs once. The build command is
+    /// not run, as it would run as root in the tree of the developer.
+    pub fn link(&self, args: std::env::Args) {
+        self.link_all(args.skip(2).collect());
+    }
+
+    fn link_all(&self, paths: Vec<String>) {
+        if paths.is_empty() {
+            println!("Please specify the folder of the plugin to link!");
+        }
+        for path in paths {
+            print!("Linking {}...", path);
+            let mut plugin = match Plugin::ad_hoc(&path) {
+                Some(plugin) if plugin.get_plugin_type() == PluginType::Local => plugin,
+                _ => {
+                    println!("Only local folders can be linked! Skipping {}!", path);
+                    continue;
+                }
+            };
+            if !Path::new(&plugin.get_location()).is_dir() {
+                println!("Cannot find plugin: {}! Skipping!", path);
+                continue;
+            }
+            let location = plugin.get_location();
+            if let Err(e) = self.name_ad_hoc_plugin(&mut plugin, &location) {
+                println!("{} Skipping {}!", e, path);
+                continue;
+            }
+
+            let plugin_path = format!("{}/{}", &self.plugin_folder_location, &plugin.get_name());
+            if fs::symlink_metadata(&plugin_path).is_ok() {
+                println!("Plugin {} is already installed!", plugin.get_name());
+                continue;
+            }
+            let plugin = match self.select_installable(&plugin) {
+                Ok(plugin) => plugin,
+                Err(e) => {
+                    println!("{} Skipping plugin {}!", e, plugin.get_name());
+                    continue;
+                }
+            };
+            self.install_missing_packages(std::iter::once(&plugin));
+            if !self.check_packages(&plugin) {
+                continue;
+            }
+            if let Err(e) =
+                self.check_manifest_requirements(&plugin.get_name(), &plugin.get_location())
+            {
+                println!("{} Skipping plugin {}!", e, plugin.get_name());
+                continue;
+            }
+            if let Err(e) = std::os::unix::fs::symlink(plugin.get_location(), &plugin_path) {
+                println!(
+                    "Error while linking {}! Skipping plugin! Error: {}",
+                    plugin.get_name(),
+                    e
+                );
+                continue;
+            }
+
+            if Path::new(&format!("{}/setup_scripts/setup.sh", plugin_path)).is_file()
+                && !self.run_setup(plugin_path)
+            {
+                println!("Error while running setup script! Please manually install {} plugin if installation is needed!", plugin.get_name());
+            }
+            let mut installed_plugin = self.installed_entry(&plugin, None);
+            installed_plugin.set_linked(true);
+            self.save_installed_plugin(installed_plugin);
+            println!("OK!")
+        }
+    }
+
+    /// Removes linked plugins, leaving the folders they link to alone.
+    pub fn unlink(&self, args: std::env::Args) {
+        self.unlink_all(args.skip(2).collect());
+    }
+
+    fn unlink_all(&self, plugin_names: Vec<String>) {
+        for plugin_name in plugin_names {
+            print!("Unlinking plugin {}...", plugin_name);
+            let mut installed_cache = self.load_installed_cache();
+            if !installed_cache
+                .get(&plugin_name)
+                .is_some_and(|x| x.is_linked())
+            {
+                println!("Plugin {} is not linked!", plugin_name);
+                continue;
+            }
+
+            let plugin_path = format!("{}/{}", &self.plugin_folder_location, plugin_name);
+            if fs::symlink_metadata(&plugin_path).is_ok_and(|x| x.file_type().is_symlink()) {
+                if let Err(e) = fs::remove_file(&plugin_path) {
+                    println!("Error while unlinking {}! Error: {}", plugin_name, e);
+                    continue;
+                }
+            }
+            installed_cache.remove(&plugin_name);
+            match installed_cache.save() {
+                Ok(_) => println!("Ok!"),
+                Err(e) => println!(
+                    "Error while writing to installed plugin cache! Error: {}",
+                    e
+                ),
+            }
+        }
+    }
+
+    /// Mirrors changes of the folder of a local plugin into its installed
+    /// copy until interrupted, running the reload command of its manifest
+    /// after each batch of changes.
+    pub fn watch(&self, mut args: std::env::Args) {
+        let plugin_name = match args.nth(3) {
+            Some(plugin_name) => plugin_name,
+            None => {
+                println!("Please specify the plugin to watch!");
+                return;
+            }
+        };
+        let plugin = match self.resolve_installed_plugin(&plugin_name) {
+            Some(plugin) if plugin.get_plugin_type() == PluginType::Local => plugin,
+            Some(_) => {
+                println!("Only local plugins can be watched!");
+                return;
+            }
+            None => {
+                println!("No plugin named {}!", plugin_name);
+                return;
+            }
+        };
+        let plugin_path = format!("{}/{}", &self.plugin_folder_location, plugin.get_name());
+        if fs::symlink_metadata(&plugin_path).is_ok_and(|x| x.file_type().is_symlink()) {
+            println!(
+                "Plugin {} is linked, its changes show up without watching!",
+                plugin.get_name()
+            );
+            return;
+        }
+        if !Path::new(&plugin_path).is_dir() {
+            println!("Plugin {} is not installed!", plugin.get_name());
+            return;
+        }
+
+        println!(
+            "Watching {} for changes of {}, press Ctrl+C to stop...",
+            plugin.get_location(),
+            plugin.get_name()
+        );
+        let source = Path::new(&plugin.get_location()).to_path_buf();
+        let result = watch::watch(&source, |changes| {
+            // The manifest is read for each batch, as it may be edited too.
+            let preserve = match Manifest::load(&source) {
+                Ok(manifest) => manifest.get_preserve(),
+                Err(e) => {
+                    println!("{} Skipping changes!", e);
+                    return;
+                }
+            };
+            for change in changes {
+                match mirror::mirror_path(&source, Path::new(&plugin_path), &change, &preserve) {
+                    Ok(sync) => println!("\t{}", sync),
+                    Err(e) => println!("Error while syncing {}! Error: {}", change.display(), e),
+                }
+            }
+            self.run_reload(&plugin_path);
+        });
+        if let Err(e) = result {
+            println!(
+                "Error while watching {}! Error: {}",
+                plugin.get_location(),
+                e
+            );
+        }
+    }
+
+    /// Runs the reload command from the manifest of a plugin, if it has one.
+    fn run_reload(&self, plugin_path: &str) {
+        let reload = match Manifest::load(Path::new(plugin_path)) {
+            Ok(manifest) => manifest.get_reload(),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        if let Some(reload) = reload {
+            print!("Reloading...");
+            match Command::new("sh")
+                .arg("-c")
+                .arg(&reload)
+                .current_dir(plugin_path)
+                .status()
+            {
+                Ok(status) if status.success() => println!("OK!"),
+                Ok(status) => println!("Reload command failed with {}!", status),
+                Err(e) => println!("Error running reload command! Error: {}", e),
+            }
+        }
+    }
+
+    /// Clones a git plugin into a hidden folder next to the plugin folder,
+    /// returning that folder.
+    fn stage_git_plugin(&self, plugin: &Plugin) -> Result<String, String> {
+        let plugin_path = format!("{}/{}", &self.plugin_folder_location, &plugin.get_name());
+        let staging_path = atomic::sibling_path(&plugin_path, "new");
+
+        if Path::new(&staging_path).exists() {
+            fs::remove_dir_all(&staging_path).map_err(|e| e.to_string())?;
+        }
+        fs::create_dir_all(&staging_path).map_err(|e| e.to_string())?;
+        let cloned = self
+            .vcs
+            .init(&staging_path, &plugin.get_location(), None)
+            .and_then(|_| self.fetch_ref(plugin, &staging_path, false));
+        if let Err(e) = cloned {
+            let _ = fs::remove_dir_all(&staging_path);
+            return Err(e);
+        }
+        Ok(staging_path)
+    }
+
+    /// Lists the system packages the plugins need that are not installed, and
+    /// installs them with the package command when one is configured.
+    fn install_missing_packages<'a>(&self, plugins: impl Iterator<Item = &'a Plugin>) {
+        let database = PackageDatabase::load(&self.root_prefix);
+        let mut missing: Vec<(String, Vec<String>)> = Vec::new();
+        for plugin in plugins {
+            for package in plugin.get_packages() {
+                if database.is_installed(&package) {
+                    continue;
+                }
+                match missing.iter_mut().find(|(x, _)| *x == package) {
+                    Some((_, needed_by)) => needed_by.push(plugin.get_name()),
+                    None => missing.push((package, vec![plugin.get_name()])),
+                }
+            }
+        }
+        if missing.is_empty() {
+            return;
+        }
+
+        println!("System packages to install:");
+        for (package, needed_by) in &missing {
+            println!("\t{} (needed by {})", package, needed_by.join(", "));
+        }
+        match &self.package_command {
+            Some(command) => {
+                let packages: Vec<String> = missing.into_iter().map(|(x, _)| x).collect();
+                if let Err(e) = packages::install(command, &packages) {
+                    println!("Error while installing system packages! Error: {}", e);
+                }
+            }
+            None => println!(
+                "Warning! No package_command is set! Plugins needing these packages are skipped!"
+            ),
+        }
+    }
+
+    /// Checks that the system packages the plugin needs are installed.
+    fn check_packages(&self, plugin: &Plugin) -> bool {
+        let database = PackageDatabase::load(&self.root_prefix);
+        let missing: Vec<String> = plugin
+            .get_packages()
+            .into_iter()
+            .filter(|x| !database.is_installed(x))
+            .collect();
+        if missing.is_empty() {
+            return true;
+        }
+
+        println!(
+            "Missing system packages {}! Skipping plugin {}!",
+            missing.join(", "),
+            plugin.get_name()
+        );
+        false
+    }
+
+    /// Picks the build of a plugin for this node and checks the requirements
+    /// of its repo entry, so only installable plugins have their system
+    /// packages installed.
+    fn select_installable(&self, plugin: &Plugin) -> Result<Plugin, String> {
+        let plugin = self.select_build(plugin)?;
+        self.check_requirements(&plugin.get_name(), plugin.get_requirements())?;
+        Ok(plugin)
+    }
+
+    /// Installs a plugin picked by `select_installable`, a given ref
+    /// overrides the ref of the repo entry and is remembered for upgrades.
+    fn install_plugin(&self, name: &str, plugin: &Plugin, git_ref: Option<String>) {
+        print!("Installing plugin {}...", name);
+        if !self.check_packages(plugin) {
+            return;
+        }
+        match plugin.get_plugin_type() {
+            PluginType::Local | PluginType::Archive if git_ref.is_some() => {
+                println!("Refs are only supported by git plugins! Skipping {}", name)
+            }
+            PluginType::Local => self.install_local_plugin(plugin),
+            PluginType::Archive => self.install_archive_plugin(plugin),
+            PluginType::Repo => {
+                let mut plugin = plugin.clone();
+                if git_ref.is_some() {
+                    plugin.set_git_ref(git_ref.clone());
+                }
+                self.install_git_plugin(&plugin, git_ref)
+            }
+            _ => {
+                println!("Wrong plugin type! Skipping {}", name)
+            }
+        }
+    }
+
+    /// Checks the hardware and resources a plugin needs. Unmet requirements
+    /// fail the install, with the warn requirement policy they are listed
+    /// and the plugin is installed anyway.
+    fn check_requirements(
+        &self,
+        plugin_name: &str,
+        requirements: &Requirements,
+    ) -> Result<(), String> {
+        let unmet = requirements.unmet(
+            &System::new(&self.root_prefix),
+            &self.plugin_folder_location,
+        );
+        if unmet.is_empty() {
+            return Ok(());
+        }
+
+        if self.requirement_policy == RequirementPolicy::Refuse {
+            return Err(format!("Requirements not met: {}!", unmet.join(", ")));
+        }
+        println!(
+            "Warning! Requirements of plugin {} not met! Installing anyway!",
+            plugin_name
+        );
+        for requirement in unmet {
+            println!("\t{}", requirement);
+        }
+        Ok(())
+    }
+
+    /// Checks the requirements declared in the manifest of a fetched plugin,
+    /// before it is built.
+    fn check_manifest_requirements(
+        &self,
+        plugin_name: &str,
+        plugin_path: &str,
+    ) -> Result<(), String> {
+        let manifest = Manifest::load(Path::new(plugin_path))?;
+        self.check_requirements(plugin_name, manifest.get_requirements())
+    }
+
+    /// Picks the build of the plugin for the platform of this node.
+    fn select_build(&self, plugin: &Plugin) -> Result<Plugin, String> {
+        plugin.for_platform(&self.platform).ok_or_else(|| {
+            let platforms: Vec<String> = plugin
+                .get_builds()
+                .iter()
+                .map(|x| x.get_platform())
+                .collect();
+            format!(
+                "No build for {}! Builds exist for {}",
+                self.platform,
+                platforms.join(", ")
+            )
+        })
+    }
+
+    /// Finds a plugin by its plain or repo-qualified (`repo/plugin`) name.
+    /// Plain names resolve to the entry of the repo with the highest
+    /// precedence, with a warning when other repos define it too.
+    fn resolve_plugin(&self, name: &str) -> Option<&Plugin> {
+        let (repo, plugin_name) = split_qualified_name(name);
+        let shadowed_plugins = self.shadowed.get(plugin_name);
+
+        match repo {
+            Some(repo) => self
+                .plugins
+                .get(plugin_name)
+                .into_iter()
+                .chain(shadowed_plugins.into_iter().flatten())
+                .find(|x| x.get_repo() == repo),
+            None => {
+                let plugin = self.plugins.get(plugin_name)?;
+                if let Some(shadowed_plugins) = shadowed_plugins {
+                    let repos: Vec<String> =
+                        shadowed_plugins.iter().map(|x| x.get_repo()).collect();
+                    println!(
+                        "Warning! Plugin {} is also defined in {}! Using the one from {}, use <repo>/{} to choose another one!",
+                        plugin_name,
+                        repos.join(", "),
+                        plugin.get_repo(),
+                        plugin_name
+                    );
+                }
+                Some(plugin)
+            }
+        }
+    }
+
+    /// Resolves a plugin for upgrading, following the repo it was installed
+    /// from unless the name is qualified with another repo.
+    fn resolve_installed_plugin(&self, name: &str) -> Option<Plugin> {
+        if let (None, plugin_name) = split_qualified_name(name) {
+            let installed_cache = self.load_installed_cache();
+            let installed_plugin = installed_cache.get(plugin_name);
+
+            if let Some((installed_plugin, (plugin_type, origin))) =
+                installed_plugin.and_then(|x| Some((x, x.get_origin()?)))
+            {
+                let mut plugin = Plugin::new(
+                    plugin_name.to_string(),
+                    true,
+                    plugin_type,
+                    origin,
+                    AD_HOC_REPO.to_string(),
+                );
+                // Without a repo entry the packages are the ones recorded
+                // from the manifest on install.
+                plugin.set_packages(installed_plugin.get_packages());
+                return Some(plugin);
+            }
+            if let Some(repo) = installed_plugin.and_then(|x| x.get_repo()) {
+                return self
+                    .resolve_plugin(&format!("{}/{}", repo, plugin_name))
+                    .cloned();
+            }
+        }
+
+        self.resolve_plugin(name).cloned()
+    }
+
+    /// Runs the setup script of a copied plugin when it has one. The plugin
+    /// stays installed when the script fails.
+    fn setup_plugin(&self, plugin_name: &str, plugin_path: &str) {
+        if Path::new(&format!("{}/setup_scripts/setup.sh", plugin_path)).is_file()
+            && !self.run_setup(plugin_path.to_string())
+        {
+            println!("Error while running setup script! Plugin is copied to plugin folder! Please manually install {} plugin if installation is needed!", plugin_name);
+        }
+    }
+
+    fn run_setup(&self, plugin_location: String) -> bool {
+        if let Ok(status) =
+            Command::new(format!("{}/setup_scripts/setup.sh", plugin_location)).status()
+        {
+            if let Some(code) = status.code() {
+                return code == 0;
+            }
+            println!("Error getting status code from setup script!");
+            return false;
+        }
+        println!("Error running setup script!");
+        false
+    }
+
+    /// Runs the build command from the manifest of a plugin in its folder,
+    /// logging the output to the log of the plugin. Plugins without a build
+    /// command pass.
+    fn build_plugin(&self, plugin_name: &str, plugin_path: &str) -> Result<(), String> {
+        let manifest = Manifest::load(Path::new(plugin_path))?;
+        let build = match manifest.get_build() {
+            Some(build) => build,
+            None => return Ok(()),
+        };
+
+        print!("Building...");
+        let log_path = format!("{}/{}.log", &self.log_folder_location, plugin_name);
+        builder::run(
+            &build,
+            Path::new(plugin_path),
+            Path::new(&log_path),
+            manifest.get_build_timeout(),
+        )
+        .map_err(|e| format!("Build failed! {}! See {} for the output!", e, log_path))
+    }
+
+    /// Replaces the plugin folder with a new one. The installed copy is
+    /// moved aside first and restored if the new one cannot be moved in.
+    fn replace_plugin_folder(&self, plugin_path: &str, new_path: &str) -> Result<(), String> {
+        let old_path = atomic::sibling_path(plugin_path, "old");
+        if Path::new(&old_path).exists() {
+            let _ = fs::remove_dir_all(&old_path);
+        }
+        fs::rename(plugin_path, &old_path)
+            .and_then(|_| {
+                fs::rename(new_path, plugin_path).inspect_err(|_| {
+                    let _ = fs::rename(&old_path, plugin_path);
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        if let Err(e) = fs::remove_dir_all(&old_path) {
+            println!("Warning! Cannot remove {}! Error: {}", old_path, e);
+        }
+        Ok(())
+    }
+
+    /// Checks a plugin against the checksum pinned in its repo entry, plugins
+    /// without a pinned checksum always pass.
+    fn verify_checksum(&self, plugin: &Plugin, path: &str) -> Result<(), String> {
+        if let Some(expected) = plugin.get_sha256() {
+            let actual = checksum::sha256_dir(Path::new(path))?;
+            if !actual.eq_ignore_ascii_case(&expected) {
+                return Err(format!(
+                    "Checksum mismatch! Expected {}, got {}",
+                    expected, actual
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Folder a git plugin with a `subdir` is cloned into, its plugin folder
+    /// is a copy of the subdirectory.
+    fn git_source_path(&self, plugin_name: &str) -> String {
+        format!("{}/{}", &self.source_folder_location, plugin_name)
+    }
+
+    /// Removes the plugin folder, and the clone of a git plugin with a
+    /// `subdir`.
+    fn remove_plugin_files(&self, plugin_name: &str) -> std::io::Result<()> {
+        if !manifest::is_valid_name(plugin_name) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid plugin name {}", plugin_name),
+            ));
+        }
+
+        let plugin_path = format!("{}/{}", &self.plugin_folder_location, plugin_name);
+        let source_path = self.git_source_path(plugin_name);
+
+        if fs::symlink_metadata(&plugin_path).is_ok_and(|x| x.file_type().is_symlink()) {
+            fs::remove_file(&plugin_path)?;
+        } else if Path::new(&plugin_path).exists() {
+            fs::remove_dir_all(&plugin_path)?;
+        }
+        if Path::new(&source_path).exists() {
+            fs::remove_dir_all(&source_path)?;
+        }
+        Ok(())
+    }
+
+    /// Removes a plugin that failed verification before its setup could run.
+    fn discard_plugin(&self, plugin: &Plugin, error: String) {
+        if let Err(e) = self.remove_plugin_files(&plugin.get_name()) {
+            println!(
+                "{} Cannot remove plugin {}! Please remove it manually! Error: {}",
+                error,
+                plugin.get_name(),
+                e
+            );
+        } else {
+            println!("{} Skipping plugin {}!", error, plugin.get_name());
+        }
+    }
+
+    fn install_local_plugin(&self, plugin: &Plugin) {
+        if Path::is_dir(Path::new(&plugin.get_location())) {
+            let plugin_path = format!("{}/{}", &self.plugin_folder_location, &plugin.get_name());
+
+            if Path::is_dir(Path::new(&plugin_path)) {
+                println!("Plugin {} is already installed!", plugin.get_name());
+            } else {
+                // The copy is built next to the plugin folder, so a failed
+                // build never shows up in the web root.
+                // Links are copied as links, so the copy has the checksum of
+                // the folder.
+                let staging_path = atomic::sibling_path(&plugin_path, "new");
+                if let Err(e) = stage_copy(&plugin.get_location(), &staging_path) {
+                    let _ = fs::remove_dir_all(&staging_path);
+                    println!(
+                        "Error while installing {}! Skipping plugin! Error: {}",
+                        plugin.get_name(),
+                        e
+                    )
+                } else if let Err(e) = self
+                    .verify_checksum(plugin, &staging_path)
+                    .and_then(|_| {
+                        self.check_manifest_requirements(&plugin.get_name(), &staging_path)
+                    })
+                    .and_then(|_| self.build_plugin(&plugin.get_name(), &staging_path))
+                    .and_then(|_| {
+                        fs::rename(&staging_path, &plugin_path).map_err(|e| e.to_string())
+                    })
+                {
+                    let _ = fs::remove_dir_all(&staging_path);
+                    println!("{} Skipping plugin {}!", e, plugin.get_name());
+                } else {
+                    self.setup_plugin(&plugin.get_name(), &plugin_path);
+                    self.add_to_installed_cache(plugin, None);
+                    println!("OK!")
+                }
+            }
+        } else {
+            println!("Cannot find plugin: {}! Skipping!", plugin.get_name());
+        }
+    }
+
+    /// Clones a git plugin next to the plugin folder, or into the sources
+    /// folder for a plugin with a `subdir` whose copy is staged instead, and
+    /// builds it there. The plugin only shows up in the web root once built.
+    fn install_git_plugin(&self, plugin: &Plugin, git_ref: Option<String>) {
+        let plugin_path = format!("{}/{}", &self.plugin_folder_location, &plugin.get_name());
+        let staging_path = atomic::sibling_path(&plugin_path, "new");
+        let clone_path = match plugin.get_subdir() {
+            Some(_) => self.git_source_path(&plugin.get_name()),
+            None => staging_path.clone(),
+        };
+
+        if Path::new(&plugin_path).exists() || Path::new(&self.git_clone_path(plugin)).exists() {
+            println!("Plugin {} is already installed!", plugin.get_name());
+            return;
+        }
+        if Path::new(&staging_path).exists() {
+            let _ = fs::remove_dir_all(&staging_path);
+        }
+        if let Err(e) = self
+            .clone_git_plugin(plugin, &clone_path, &staging_path)
+            .and_then(|_| self.check_manifest_requirements(&plugin.get_name(), &staging_path))
+            .and_then(|_| self.build_plugin(&plugin.get_name(), &staging_path))
+            .and_then(|_| fs::rename(&staging_path, &plugin_path).map_err(|e| e.to_string()))
+        {
+            let _ = fs::remove_dir_all(&staging_path);
+            self.discard_plugin(plugin, e);
+            return;
+        }
+
+        self.setup_plugin(&plugin.get_name(), &plugin_path);
+        self.add_to_installed_cache(plugin, git_ref);
+        println!("OK!")
+    }
+
+    /// Creates a shallow clone of only the ref of the plugin. A plugin with a
+    /// `subdir` only gets that folder checked out, and it is copied into the
+    /// plugin folder.
+    fn clone_git_plugin(
+        &self,
+        plugin: &Plugin,
+        clone_path: &str,
+        plugin_path: &str,
+    ) -> Result<(), String> {
+        fs::create_dir_all(clone_path).map_err(|e| e.to_string())?;
+        self.vcs.init(
+            clone_path,
+            &plugin.get_location(),
+            plugin.get_subdir().as_deref(),
+        )?;
+
+        self.fetch_ref(plugin, clone_path, false)?;
+
+        if plugin.get_subdir().is_some() {
+            self.mirror_subdir(plugin, clone_path, plugin_path)?;
+        }
+        Ok(())
+    }
+
+    /// Copies the `subdir` of the clone of a git plugin into its plugin
+    /// folder. The plugin folder is a copy rather than a link into the clone,
+    /// as web servers may not follow links.
+    fn mirror_subdir(
+        &self,
+        plugin: &Plugin,
+        clone_path: &str,
+        plugin_path: &str,
+    ) -> Result<(), String> {
+        let subdir = plugin.get_subdir().unwrap_or_default();
+        let subdir_path = Path::new(clone_path).join(&subdir);
+        if !subdir_path.is_dir() {
+            return Err(format!("No folder {} in the repository!", subdir));
+        }
+        // Earlier versions linked the plugin folder to the subdir.
+        if fs::symlink_metadata(plugin_path).is_ok_and(|x| x.file_type().is_symlink()) {
+            fs::remove_file(plugin_path).map_err(|e| e.to_string())?;
+        }
+
+        let manifest = Manifest::load(&subdir_path)?;
+        fs::create_dir_all(plugin_path).map_err(|e| e.to_string())?;
+        mirror::mirror_tree(
+            &subdir_path,
+            Path::new(plugin_path),
+            &manifest.get_preserve(),
+        )?;
+        Ok(())
+    }
+
+    fn install_archive_plugin(&self, plugin: &Plugin) {
+        let plugin_path = format!("{}/{}", &self.plugin_folder_location, &plugin.get_name());
+
+        if Path::new(&plugin_path).exists() {
+            println!("Plugin {} is already installed!", plugin.get_name());
+            return;
+        }
+        let (staging_path, root_path, sha256) = match self.stage_archive_plugin(plugin) {
+            Ok(staged) => staged,
+            Err(e) => {
+                println!("{} Skipping plugin {}!", e, plugin.get_name());
+                return;
+            }
+        };
+        if let Err(e) = self
+            .check_manifest_requirements(&plugin.get_name(), &root_path)
+            .and_then(|_| self.build_plugin(&plugin.get_name(), &root_path))
+        {
+            let _ = fs::remove_dir_all(&staging_path);
+            println!("{} Skipping plugin {}!", e, plugin.get_name());
+            return;
+        }
+        let moved = fs::rename(&root_path, &plugin_path);
+        let _ = fs::remove_dir_all(&staging_path);
+        if let Err(e) = moved {
+            println!(
+                "Error while installing {}! Skipping plugin! Error: {}",
+                plugin.get_name(),
+                e
+            );
+            return;
+        }
+
+        self.setup_plugin(&plugin.get_name(), &plugin_path);
+        self.add_archive_to_installed_cache(plugin, sha256);
+        println!("OK!")
+    }
+
+    /// Downloads the archive of a plugin, checks it against the pinned
+    /// checksum and extracts it into a hidden folder next to the plugin
+    /// folder. Returns that folder, the folder holding the plugin content
+    /// (the single top level folder of the archive, if it has one) and the
+    /// checksum of the archive.
+    fn stage_archive_plugin(&self, plugin: &Plugin) -> Result<(String, String, String), String> {
+        let location = plugin.get_location();
+        let format = ArchiveFormat::from_location(&location)
+            .ok_or(format!("Unknown archive format of {}!", location))?;
+        let plugin_path = format!("{}/{}", &self.plugin_folder_location, &plugin.get_name());
+        let staging_path = atomic::sibling_path(&plugin_path, "new");
+        let download_path = atomic::sibling_path(&plugin_path, "download");
+
+        if Path::new(&staging_path).exists() {
+            fs::remove_dir_all(&staging_path).map_err(|e| e.to_string())?;
+        }
+        let sha256 = archive::download(
+            &location,
+            Path::new(&download_path),
+            self.credentials.get(&location),
+        )
+        .map_err(|e| format!("Cannot download {}! Error: {}", location, e))
+        .and_then(|sha256| match plugin.get_sha256() {
+            Some(expected) if !expected.eq_ignore_ascii_case(&sha256) => Err(format!(
+                "Checksum mismatch! Expected {}, got {}",
+                expected, sha256
+            )),
+            _ => Ok(sha256),
+        })
+        .and_then(|sha256| {
+            fs::create_dir(&staging_path)
+                .map_err(|e| e.to_string())
+                .and_then(|_| {
+                    archive::extract(Path::new(&download_path), format, Path::new(&staging_path))
+                })
+                .map_err(|e| format!("Cannot extract archive! Error: {}", e))?;
+            Ok(sha256)
+        });
+        let _ = fs::remove_file(&download_path);
+        let sha256 = match sha256 {
+            Ok(sha256) => sha256,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&staging_path);
+                return Err(e);
+            }
+        };
+
+        let entries: Vec<_> = read_dir(&staging_path)
+            .map_err(|e| e.to_string())?
+            .filter_map(|x| x.ok())
+            .collect();
+        let root_path = match entries.as_slice() {
+            [entry] if entry.file_type().is_ok_and(|x| x.is_dir()) => {
+                entry.path().display().to_string()
+            }
+            _ => staging_path.clone(),
+        };
+        Ok((staging_path, root_path, sha256))
+    }
+
+    pub fn upgrade(&self, args: std::env::Args) {
+        if args.len() < 3 {
+            self.upgrade_all(
+                self.get_installed_plugins()
+                    .iter()
+                    .map(|x| x.get_name())
+                    .collect(),
+            );
+        } else {
+            self.upgrade_all(args.skip(2).collect());
+        }
+    }
+
+    pub fn upgrade_local_plugin(&self, plugin: &Plugin) -> bool {
+        if Path::is_dir(Path::new(&plugin.get_location())) {
+            let plugin_path = format!("{}/{}", &self.plugin_folder_location, &plugin.get_name());
+
+            if Path::is_dir(Path::new(&plugin_path)) && plugin.get_name() != "" {
+                if let Err(e) = self.verify_checksum(plugin, &plugin.get_location()) {
+                    println!("{} Skipping plugin {}!", e, plugin.get_name());
+                    return false;
+                }
+
+                match self.mirror_local_plugin(plugin, &plugin_path) {
+                    Ok(report) => {
+                        println!("OK! {}", report);
+                        return true;
+                    }
+                    Err(e) => println!("{} Skipping plugin {}!", e, plugin.get_name()),
+                }
+            } else {
+                println!(
+                    "Plugin {} is not installed so it cannot be upgraded!",
+                    plugin.get_name()
+                );
+            }
+        } else {
+            println!("Cannot find plugin: {}! Skipping!", plugin.get_name());
+        }
+        false
+    }
+
+    /// Mirrors the folder of a local plugin into its installed copy. A plugin
+    /// with a build command is mirrored into a copy next to the plugin folder
+    /// and built there, so the installed copy is only replaced by a built one.
+    fn mirror_local_plugin(
+        &self,
+        plugin: &Plugin,
+        plugin_path: &str,
+    ) -> Result<mirror::MirrorReport, String> {
+        let source = Path::new(&plugin.get_location()).to_path_buf();
+        let manifest = Manifest::load(&source)?;
+        if manifest.get_build().is_none() {
+            return mirror::mirror_tree(&source, Path::new(plugin_path), &manifest.get_preserve())
+                .map_err(|e| format!("Error while upgrading! Error: {}", e));
+        }
+
+        let staging_path = atomic::sibling_path(plugin_path, "new");
+        let result = stage_copy(plugin_path, &staging_path)
+            .and_then(|_| {
+                mirror::mirror_tree(&source, Path::new(&staging_path), &manifest.get_preserve())
+            })
+            .map_err(|e| format!("Error while upgrading! Error: {}", e))
+            .and_then(|report| {
+                self.build_plugin(&plugin.get_name(), &staging_path)?;
+                self.replace_plugin_folder(plugin_path, &staging_path)
+                    .map_err(|e| format!("Error while upgrading! Error: {}", e))?;
+                Ok(report)
+            });
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&staging_path);
+        }
+        result
+    }
+
+    /// Replaces an archive plugin with a fresh download of its archive, the
+    /// installed copy stays in place until the new one is extracted.
+    pub fn upgrade_archive_plugin(&self, plugin: &Plugin) -> Option<String> {
+        let plugin_path = format!("{}/{}", &self.plugin_folder_location, &plugin.get_name());
+        if !Path::new(&plugin_path).is_dir() {
+            println!(
+                "Plugin {} is not installed so it cannot be upgraded!",
+                plugin.get_name()
+            );
+            return None;
+        }
+
+        let (staging_path, root_path, sha256) = match self.stage_archive_plugin(plugin) {
+            Ok(staged) => staged,
+            Err(e) => {
+                println!("{} Skipping plugin {}!", e, plugin.get_name());
+                return None;
+            }
+        };
+        let installed_sha256 = self
+            .load_installed_cache()
+            .get(&plugin.get_name())
+            .and_then(|x| x.get_sha256());
+        if installed_sha256.is_some_and(|x| x == sha256) {
+            let _ = fs::remove_dir_all(&staging_path);
+            println!("OK! Already up to date");
+            return Some(sha256);
+        }
+
+        if let Err(e) = self.build_plugin(&plugin.get_name(), &root_path) {
+            let _ = fs::remove_dir_all(&staging_path);
+            println!("{} Skipping plugin {}!", e, plugin.get_name());
+            return None;
+        }
+
+        let replaced = self.replace_plugin_folder(&plugin_path, &root_path);
+        let _ = fs::remove_dir_all(&staging_path);
+        if let Err(e) = replaced {
+            println!(
+                "Error while upgrading {}! Skipping plugin! Error: {}",
+                plugin.get_name(),
+                e
+            );
+            return None;
+        }
+
+        println!("OK!");
+        Some(sha256)
+    }
+
+    /// Fetches the latest commit of the ref of the plugin, its pinned commit
+    /// when it has no ref, or else the default branch, and checks it out.
+    /// Only that commit is downloaded, unless the remote refuses to send a
+    /// single commit. Branches are checked out as a local branch, tags and
+    /// commits detached. A pinned commit has to be exactly the checked out
+    /// one.
+    fn fetch_ref(&self, plugin: &Plugin, path: &str, force: bool) -> Result<(), String> {
+        // A pinned commit is checked out as it is, the ref of the entry only
+        // matters once the pin is dropped.
+        let remote_ref = match plugin.get_commit() {
+            Some(commit) => RemoteRef::Commit(commit),
+            None => self
+                .vcs
+                .resolve_remote_ref(path, plugin.get_git_ref().as_deref())?,
+        };
+        self.vcs.fetch(path, &remote_ref)?;
+        self.vcs
+            .checkout(path, &remote_ref, plugin.get_subdir().as_deref(), force)?;
+
+        if let Some(commit) = plugin.get_commit() {
+            let head = self.vcs.head(path)?;
+            if !head.eq_ignore_ascii_case(&commit) {
+                return Err(format!(
+                    "Commit mismatch! Expected {}, got {}",
+                    commit, head
+                ));
+            }
+        }
+
+        if plugin.is_submodules_enabled() {
+            self.vcs
+                .update_submodules(path, plugin.get_subdir().as_deref(), force)?;
+        }
+        Ok(())
+    }
+
+    /// Folder of the git repository of an installed git plugin.
+    fn git_clone_path(&self, plugin: &Plugin) -> String {
+        match plugin.get_subdir() {
+            Some(_) => self.git_source_path(&plugin.get_name()),
+            None => format!("{}/{}", &self.plugin_folder_location, plugin.get_name()),
+        }
+    }
+
+    /// Upgrades a copy of the clone of a git plugin, builds it and swaps it
+    /// in, so a failed checkout or build leaves the installed plugin and its
+    /// recorded commit alone. The copy of a plugin with a `subdir` is staged
+    /// from its plugin folder, keeping the preserved paths.
+    pub fn upgrade_git_plugin(&self, plugin: &Plugin) -> bool {
+        let plugin_path = format!("{}/{}", &self.plugin_folder_location, &plugin.get_name());
+        let clone_path = self.git_clone_path(plugin);
+        let staging_path = atomic::sibling_path(&plugin_path, "new");
+        let clone_staging_path = atomic::sibling_path(&clone_path, "new");
+        let is_subdir = plugin.get_subdir().is_some();
+
+        let upgraded = stage_copy(&clone_path, &clone_staging_path)
+            .and_then(|_| self.upgrade_git_clone(plugin, &clone_staging_path))
+            .and_then(|message| {
+                if !self.is_git_clone_changed(&clone_path, &clone_staging_path) {
+                    return Ok(message);
+                }
+                if is_subdir {
+                    if fs::symlink_metadata(&plugin_path).is_ok_and(|x| x.is_dir()) {
+                        stage_copy(&plugin_path, &staging_path)?;
+                    } else if Path::new(&staging_path).exists() {
+                        fs::remove_dir_all(&staging_path).map_err(|e| e.to_string())?;
+                    }
+                    self.mirror_subdir(plugin, &clone_staging_path, &staging_path)?;
+                }
+                let built_path = if is_subdir {
+                    &staging_path
+                } else {
+                    &clone_staging_path
+                };
+                self.build_plugin(&plugin.get_name(), built_path)?;
+                self.replace_plugin_folder(&plugin_path, built_path)
+                    .map_err(|e| format!("Error while upgrading! Error: {}", e))?;
+                if is_subdir {
+                    self.replace_plugin_folder(&clone_path, &clone_staging_path)
+                        .map_err(|e| format!("Error while upgrading the clone! Error: {}", e))?;
+                }
+                Ok(message)
+            });
+        for path in [&staging_path, &clone_staging_path] {
+            if Path::new(path).exists() {
+                let _ = fs::remove_dir_all(path);
+            }
+        }
+        match upgraded {
+            Ok(message) => {
+                println!("{}", message);
+                true
+            }
+            Err(e) => {
+                let (error, details) = e.split_once('\n').unwrap_or((&e, ""));
+                println!("{} Skipping plugin {}!", error, plugin.get_name());
+                if !details.is_empty() {
+                    println!("{}", details);
+                }
+                false
+            }
+        }
+    }
+
+    /// Whether the staged clone of a git plugin has another commit or other
+    /// local changes than the installed clone, unchanged plugins are not
+    /// rebuilt.
+    fn is_git_clone_changed(&self, clone_path: &str, staging_path: &str) -> bool {
+        let state = |path: &str| {
+            (
+                self.vcs.head(path).ok(),
+                self.vcs.submodules(path).ok(),
+                self.vcs
+                    .status(path, true)
+                    .ok()
+                    .map(|x| x.iter().map(|x| x.to_string()).collect::<Vec<_>>()),
+            )
+        };
+        let (installed, staged) = (state(clone_path), state(staging_path));
+        installed.0.is_none() || installed != staged
+    }
+
+    /// Upgrades the clone of a git plugin. Local changes, and local commits
+    /// made after the last install or upgrade, are handled with the dirty
+    /// policy.
+    fn upgrade_git_clone(&self, plugin: &Plugin, path: &str) -> Result<String, String> {
+        let changes = self.vcs.status(path, self.block_untracked)?;
+        let head = self.vcs.head(path)?;
+        let installed_commit = self
+            .load_installed_cache()
+            .get(&plugin.get_name())
+            .and_then(|x| x.get_commit());
+        let diverged = installed_commit
+            .as_ref()
+            .is_some_and(|x| !x.eq_ignore_ascii_case(&head));
+
+        if changes.is_empty() && !diverged {
+            self.fetch_ref(plugin, path, false)?;
+            return Ok(String::from("OK!"));
+        }
+
+        match self.dirty_policy {
+            DirtyPolicy::Abort => {
+                let mut summary = Vec::new();
+                if let (true, Some(installed_commit)) = (diverged, &installed_commit) {
+                    summary.push(format!(
+                        "\tlocal commits: {} is checked out instead of {}",
+                        head, installed_commit
+                    ));
+                }
+                match self.vcs.diff_summary(path) {
+                    Ok(diff) => summary.extend(diff.lines().map(|x| format!("\t{}", x.trim()))),
+                    Err(_) => summary.extend(
+                        changes
+                            .iter()
+                            .filter(|x| !x.is_untracked())
+                            .map(|x| format!("\t{}", x)),
+                    ),
+                }
+                for change in changes.iter().filter(|x| x.is_untracked()) {
+                    summary.push(format!("\t{}", change));
+                }
+                Err(format!(
+                    "Local changes found! Set dirty_policy to stash or reset to upgrade anyway!\n{}",
+                    summary.join("\n")
+                ))
+            }
+            DirtyPolicy::Stash => {
+                if diverged {
+                    return Err(String::from(
+                        "Local commits found! Only the reset dirty policy replaces them!",
+                    ));
+                }
+                self.vcs.stash(path, self.block_untracked)?;
+                // The upgrade runs on a staged copy, so the installed plugin
+                // keeps its local changes when the fetch fails.
+                self.fetch_ref(plugin, path, false)?;
+                match self.vcs.stash_pop(path) {
+                    Ok(_) => Ok(String::from("OK! Local changes were stashed and reapplied")),
+                    Err(e) => Ok(format!(
+                        "OK! Local changes could not be reapplied and are kept in the stash! Error: {}",
+                        e
+                    )),
+                }
+            }
+            DirtyPolicy::Reset => {
+                let backup_path = self.backup_changes(plugin, path, &changes)?;
+                self.fetch_ref(plugin, path, true)?;
+                // New files are left alone by the checkout, they are backed up
+                // like the other changes.
+                for change in changes.iter().filter(|x| x.is_new()) {
+                    if let Err(e) = fs::remove_file(Path::new(path).join(change.get_path())) {
+                        println!("Warning! Cannot remove {}! Error: {}", change.get_path(), e);
+                    }
+                }
+
+                let mut message = String::from("OK! Local changes were reset");
+                if !changes.is_empty() {
+                    message.push_str(&format!(", changed files are backed up in {}", backup_path));
+                }
+                if diverged {
+                    message.push_str(&format!(", local commit {} was dropped", head));
+                }
+                Ok(message)
+            }
+        }
+    }
+
+    /// Copies the changed files of a git plugin into the backup folder before
+    /// they are reset, returning the folder of the backup.
+    fn backup_changes(
+        &self,
+        plugin: &Plugin,
+        path: &str,
+        changes: &[Change],
+    ) -> Result<String, String> {
+        let backup_path = format!(
+            "{}/{}-{}",
+            &self.backup_folder_location,
+            plugin.get_name(),
+            Local::now().format("%Y%m%d%H%M%S")
+        );
+
+        for change in changes.iter().filter(|x| !x.is_deleted()) {
+            let target = Path::new(&backup_path).join(change.get_path());
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            fs::copy(Path::new(path).join(change.get_path()), &target)
+                .map_err(|e| format!("Cannot back up {}! Error: {}", change.get_path(), e))?;
+        }
+        Ok(backup_path)
+    }
+
+    /// Upgrades the plugins, git plugins follow the ref given as `name@ref`,
+    /// else the ref they were installed with, else the ref of the repo entry.
+    fn upgrade_all(&self, plugins: Vec<String>) {
+        // Packages added to the plugins since they were installed are
+        // installed before upgrading, like on install.
+        let installed_cache = self.load_installed_cache();
+        let selected_plugins: Vec<Plugin> = plugins
+            .iter()
+            .filter_map(|argument| {
+                let (plugin, _) = split_ref(argument);
+                let (_, plugin_name) = split_qualified_name(plugin);
+                if installed_cache
+                    .get(plugin_name)
+                    .is_some_and(|x| x.is_linked())
+                {
+                    return None;
+                }
+                self.select_build(&self.resolve_installed_plugin(plugin)?)
+                    .ok()
+            })
+            .collect();
+        self.install_missing_packages(selected_plugins.iter());
+
+        for argument in plugins {
+            let (plugin, git_ref) = split_ref(&argument);
+            if git_ref.is_some_and(|x| !is_valid_ref(x)) {
+                println!("Invalid ref in {}! Skipping!", argument);
+                continue;
+            }
+            let (_, plugin_name) = split_qualified_name(plugin);
+            if let Some((_, origin)) = self
+                .load_installed_cache()
+                .get(plugin_name)
+                .filter(|x| x.is_linked())
+                .and_then(|x| x.get_origin())
+            {
+                println!("Plugin {} is linked to {}! Skipping!", plugin, origin);
+                continue;
+            }
+
+            if let Some(seclected_plugin) = self.resolve_installed_plugin(plugin) {
+                print!("Upgrading plugin {}...", plugin);
+                let seclected_plugin = &match self.select_build(&seclected_plugin) {
+                    Ok(seclected_plugin) => seclected_plugin,
+                    Err(e) => {
+                        println!("{} Skipping plugin {}!", e, plugin);
+                        continue;
+                    }
+                };
+                if !self.check_packages(seclected_plugin) {
+                    continue;
+                }
+                match seclected_plugin.get_plugin_type() {
+                    PluginType::Local | PluginType::Archive if git_ref.is_some() => {
+                        println!(
+                            "Refs are only supported by git plugins! Skipping {}",
+                            plugin
+                        )
+                    }
+                    PluginType::Local => {
+                        if self.upgrade_local_plugin(seclected_plugin) {
+                            self.add_to_installed_cache(seclected_plugin, None);
+                        }
+                    }
+                    PluginType::Archive => {
+                        if let Some(sha256) = self.upgrade_archive_plugin(seclected_plugin) {
+                            self.add_archive_to_installed_cache(seclected_plugin, sha256);
+                        }
+                    }
+                    PluginType::Repo => {
+                        let (_, plugin_name) = split_qualified_name(plugin);
+                        let git_ref = git_ref.map(String::from).or_else(|| {
+                            self.load_installed_cache()
+                                .get(plugin_name)
+                                .and_then(|x| x.get_git_ref())
+                        });
+                        let mut seclected_plugin = seclected_plugin.clone();
+                        if git_ref.is_some() {
+                            seclected_plugin.set_git_ref(git_ref.clone());
+                        }
+                        if self.upgrade_git_plugin(&seclected_plugin) {
+                            self.add_to_installed_cache(&seclected_plugin, git_ref);
+                        }
+                    }
+                    _ => {
+                        println!("Wrong plugin type! Skipping {}", plugin)
+                    }
+                }
+            } else if self.plugins.is_empty() {
+                println!(
+                    "Skipping {}! No plugins found in repos! Please run 'rpi-mesh-plugin-manager update'!",
+                    plugin
+                );
+            } else {
+                println!("Skipping {}! No such plugin!", plugin);
+            }
+        }
+    }
+
+    fn load_installed_cache(&self) -> InstalledCache {
+        InstalledCache::load(&self.installed_cache_location).expect("Cannot read installed cache!")
+    }
+
+    /// Records the plugin as installed together with the ref it was pinned to
+    /// on the command line and, for git plugins, the checked out commit.
+    fn add_to_installed_cache(&self, plugin: &Plugin, git_ref: Option<String>) {
+        self.save_installed_plugin(self.installed_entry(plugin, git_ref));
+    }
+
+    /// Records an archive plugin as installed from the archive with the given
+    /// checksum.
+    fn add_archive_to_installed_cache(&self, plugin: &Plugin, sha256: String) {
+        let mut installed_plugin = self.installed_entry(plugin, None);
+        installed_plugin.set_sha256(Some(sha256));
+        self.save_installed_plugin(installed_plugin);
+    }
+
+    fn installed_entry(&self, plugin: &Plugin, git_ref: Option<String>) -> InstalledPlugin {
+        let mut installed_plugin = InstalledPlugin::new(plugin.get_name(), Some(plugin.get_repo()));
+        installed_plugin.set_git_ref(git_ref);
+        installed_plugin.set_packages(plugin.get_packages());
+        if plugin.get_repo() == AD_HOC_REPO {
+            installed_plugin.set_origin(Some((plugin.get_plugin_type(), plugin.get_location())));
+        }
+        if plugin.get_plugin_type() == PluginType::Repo {
+            let clone_path = self.git_clone_path(plugin);
+            installed_plugin.set_commit(self.vcs.head(&clone_path).ok());
+            if plugin.is_submodules_enabled() {
+                installed_plugin
+                    .set_submodules(self.vcs.submodules(&clone_path).unwrap_or_default());
+            }
+        }
+        installed_plugin
+    }
+
+    /// Saves the entry of a plugin, keeping the collections it was installed
+    /// with.
+    fn save_installed_plugin(&self, mut installed_plugin: InstalledPlugin) {
+        let mut installed_cache = self.load_installed_cache();
+        if let Some(previous) = installed_cache.get(&installed_plugin.get_name()) {
+            installed_plugin.set_collections(previous.get_collections());
+            installed_plugin.set_auto(previous.is_auto());
+        }
+        installed_cache.insert(installed_plugin);
+
+        if let Err(e) = installed_cache.save() {
+            println!("Cannot write to installed cache! Error: {}", e)
+        }
+    }
+
+    fn get_installed_plugins(&self) -> Vec<InstalledPlugin> {
+        self.load_installed_cache().get_plugins().clone()
+    }
+
+    pub fn uninstall(&self, args: std::env::Args) {
+        self.uninstall_all(args.skip(2).collect());
+    }
+
+    /// Uninstalls plugins and collections, using only the installed cache.
+    fn uninstall_all(&self, plugins_to_delete: Vec<String>) {
+        for plugin in plugins_to_delete {
+            let (_, plugin_name) = split_qualified_name(&plugin);
+
+            let installed_cache = self.load_installed_cache();
+            let members: Vec<&InstalledPlugin> = installed_cache
+                .get_plugins()
+                .iter()
+                .filter(|x| x.get_collections().contains(&plugin))
+                .collect();
+            if installed_cache.get(plugin_name).is_none() && !members.is_empty() {
+                println!("Uninstalling collection {}...", plugin);
+                for member in members {
+                    self.uninstall_collection_member(member, &plugin);
+                }
+            } else {
+                self.uninstall_plugin(plugin_name);
+            }
+        }
+    }
+
+    /// Removes a member of an uninstalled collection, unless the plugin was
+    /// installed by name or another collection still needs it.
+    fn uninstall_collection_member(&self, member: &InstalledPlugin, collection: &str) {
+        let collections: Vec<String> = member
+            .get_collections()
+            .into_iter()
+            .filter(|x| x != collection)
+            .collect();
+        if member.is_auto() && collections.is_empty() {
+            self.uninstall_plugin(&member.get_name());
+            return;
+        }
+
+        println!("Keeping plugin {}, it is still needed!", member.get_name());
+        let mut installed_cache = self.load_installed_cache();
+        let mut installed_plugin = member.clone();
+        installed_plugin.set_collections(collections);
+        installed_cache.insert(installed_plugin);
+        if let Err(e) = installed_cache.save() {
+            println!("Cannot write to installed cache! Error: {}", e)
+        }
+    }
+
+    fn is_installed(&self, plugin_name: &str) -> bool {
+        manifest::is_valid_name(plugin_name)
+            && Path::new(&format!("{}/{}", &self.plugin_folder_location, plugin_name)).is_dir()
+    }
+
+    /// Only plugins recorded in the installed cache are removed, anything
+    /// else in the plugin folder was not installed by the plugin manager.
+    fn uninstall_plugin(&self, plugin_name: &str) {
+        if !manifest::is_valid_name(plugin_name) {
+            println!("Error! Invalid plugin name {}!", plugin_name);
+        } else if self.load_installed_cache().get(plugin_name).is_some() {
+            print!("Uninstalling plugin {}...", plugin_name);
+
+            if let Err(e) = self.remove_plugin_files(plugin_name) {
+                println!("Error while uninstalling {}! Error: {}", plugin_name, e);
+            } else {
+                println!("Ok!");
+                let mut installed_cache = self.load_installed_cache();
+                installed_cache.remove(plugin_name);
+
+                if let Err(e) = installed_cache.save() {
+                    println!(
+                        "Error while writing to installed plugin cache! Error: {}",
+                        e
+                    );
+                }
+            }
+        } else {
+            println!("Plugin {} is not installed!", plugin_name);
+        }
+    }
+
+    pub fn update(&mut self) {
+        let mut repo_states = RepoStateCache::load(&self.repo_state_location);
+
+        for repo in &self.repos {
+            print!("Updating {} repo...", repo.get_name());
+            if repo.is_enabled() {
+                self.update_repo(repo, &mut repo_states);
+            } else {
+                println!("Skipping! Repo is disabled!");
+            }
+        }
+
+        for repo in &self.repos {
+            if repo.is_enabled() {
+                if let Err(e) = self.update_collections(
+                    &repo.get_location(),
+                    repo,
+                    &mut repo_states,
+                    &mut Vec::new(),
+                ) {
+                    println!("Error while reading repo {}! Error: {}", repo.get_name(), e);
+                }
+            }
+        }
+
+        if let Err(e) = repo_states.save() {
+            println!("Cannot write repo state! Error: {}", e);
+        }
+    }
+
+    /// Fetches the remote collections included by a repo or collection file
+    /// into the collection cache, following nested collections.
+    fn update_collections(
+        &self,
+        location: &str,
+        repo: &Repo,
+        repo_states: &mut RepoStateCache,
+        include_chain: &mut Vec<String>,
+    ) -> Result<(), String> {
+        self.enter_collection(location, include_chain)?;
+        let content = self.read_collection(location, repo);
+
+        if let Ok(content) = &content {
+            for section in Document::parse(content).get_sections() {
+                // Disabled collections are not loaded, so they are not
+                // fetched either.
+                if section.get("type").as_deref() != Some("collection")
+                    || section.get("enabled").as_deref() != Some("true")
+                {
+                    continue;
+                }
+                let collection_location = section.get("location").unwrap_or_default();
+
+                // Collections of the include chain are already fetched, the
+                // cycle is reported by the nested call below.
+                if is_remote(&collection_location)
+                    && !include_chain.contains(&self.collection_identity(&collection_location))
+                {
+                    print!("Updating {} collection...", section.get_name());
+                    if let Err(e) = self.update_collection(&collection_location, repo, repo_states)
+                    {
+                        println!("Warning! Cannot fetch collection! Error: {}", e);
+                    }
+                }
+
+                if let Err(e) =
+                    self.update_collections(&collection_location, repo, repo_states, include_chain)
+                {
+                    println!(
+                        "Warning! Skipping collection {}! Error: {}",
+                        section.get_name(),
+                        e
+                    );
+                }
+            }
+        }
+
+        include_chain.pop();
+        content.map(|_| ())
+    }
+
+    /// Fetches a remote collection into the collection cache. Like repos, it
+    /// is fetched with a conditional request while the cached copy is still
+    /// the one that was fetched last time.
+    fn update_collection(
+        &self,
+        location: &str,
+        repo: &Repo,
+        repo_states: &mut RepoStateCache,
+    ) -> Result<(), String> {
+        let cache_path = self.collection_cache_path(location);
+        let mut state = repo_states.get(&format!(
+            "{}{}",
+            COLLECTION_STATE_PREFIX,
+            remote::cache_file_name(location)
+        ));
+        let local_sha256 = fs::read(&cache_path).map(|x| sha256_hex(&x)).ok();
+        let trusted = self.is_trusted(repo);
+        let stale =
+            fs::read_to_string(&cache_path).is_ok_and(|x| Freshness::of_local_copy(&x).is_stale());
+        if local_sha256.is_none()
+            || local_sha256 != state.get_sha256()
+            || stale
+            || (trusted && self.verify_file(&cache_path, repo).is_err())
+        {
+            state.set_validators(None, None);
+        }
+
+        let content = match remote::fetch_conditional(
+            location,
+            state.get_etag(),
+            state.get_last_modified(),
+            self.credentials.get(location),
+        )? {
+            FetchResult::NotModified => {
+                println!("Up to date!");
+                return Ok(());
+            }
+            FetchResult::Fetched(content) => content,
+        };
+
+        let freshness = repo::validate(content.get_body())
+            .and_then(|x| Freshness::from_header(x.get_header()))?;
+        if let Ok(local_content) = fs::read_to_string(&cache_path) {
+            Freshness::of_local_copy(&local_content).check_newer(&freshness)?;
+        }
+        let sha256 = sha256_hex(content.get_body().as_bytes());
+        let unchanged = local_sha256.as_ref() == Some(&sha256);
+        let signature = if trusted {
+            Some(self.fetch_signature(location, content.get_body(), repo)?.0)
+        } else {
+            None
+        };
+
+        let signature_location = atomic::sibling_path(&cache_path, SIGNATURE_EXTENSION);
+        let mut files = Vec::new();
+        if !unchanged {
+            files.push((cache_path.as_str(), content.get_body()));
+        }
+        if let Some(signature) = &signature {
+            files.push((signature_location.as_str(), signature.as_str()));
+        }
+        atomic::replace_files(&files)?;
+        if let Some(valid_until) = freshness.get_valid_until() {
+            if freshness.is_expired() {
+                print!("Warning! Received collection expired on {}! ", valid_until);
+            }
+        }
+        println!("{}", if unchanged { "Up to date!" } else { "OK!" });
+
+        state.set_validators(content.get_etag(), content.get_last_modified());
+        state.set_sha256(sha256);
+        repo_states.insert(state);
+        Ok(())
+    }
+
+    /// Fetches the remote of the repo, only rewriting the local copy when the
+    /// server sent different content. Conditional headers are only sent when
+    /// the local copy is still the one that was fetched last time.
+    pub fn update_repo(&self, repo: &Repo, repo_states: &mut RepoStateCache) {
+        match repo.get_remote() {
+            Some(remote) => {
+                let mut state = repo_states.get(&repo.get_name());
+                let local_sha256 = fs::read(repo.get_location()).map(|x| sha256_hex(&x)).ok();
+                let trusted = self.is_trusted(repo);
+                if local_sha256.is_none()
+                    || local_sha256 != state.get_sha256()
+                    || repo.get_freshness().is_stale()
+                    || (trusted && self.verify_file(&repo.get_location(), repo).is_err())
+                {
+                    state.set_validators(None, None);
+                }
+
+                match remote::fetch_conditional(
+                    &remote,
+                    state.get_etag(),
+                    state.get_last_modified(),
+                    self.credentials.get(&remote),
+                ) {
+                    Ok(FetchResult::NotModified) => println!("Up to date!"),
+                    Ok(FetchResult::Fetched(content)) => {
+                        let sha256 = sha256_hex(content.get_body().as_bytes());
+                        let unchanged = local_sha256.as_ref() == Some(&sha256);
+
+                        let freshness = match repo::validate(content.get_body())
+                            .and_then(|x| Freshness::from_header(x.get_header()))
+                        {
+                            Ok(freshness) => freshness,
+                            Err(e) => {
+                                println!("Invalid repo received, keeping local copy! Error: {}", e);
+                                return;
+                            }
+                        };
+                        if let Err(e) = repo.get_freshness().check_newer(&freshness) {
+                            println!("Outdated repo received, keeping local copy! Error: {}", e);
+                            return;
+                        }
+
+                        let signature = if trusted {
+                            match self.fetch_signature(&remote, content.get_body(), repo) {
+                                Ok(signature) => Some(signature),
+                                Err(e) => {
+                                    println!("Repo is not signed by a trusted key, keeping local copy! Error: {}", e);
+                                    return;
+                                }
+                            }
+                        } else {
+                            None
+                        };
+
+                        let location = repo.get_location();
+                        let signature_location =
+                            atomic::sibling_path(&location, SIGNATURE_EXTENSION);
+                        let mut files = Vec::new();
+                        if !unchanged {
+                            files.push((location.as_str(), content.get_body()));
+                        }
+                        if let Some((signature, _)) = &signature {
+                            files.push((signature_location.as_str(), signature.as_str()));
                         }
+                        let written = atomic::replace_files(&files);
+
+                        if let Some(valid_until) = freshness.get_valid_until() {
+                            if freshness.is_expired() {
+                                print!("Warning! Received repo expired on {}! ", valid_until);
+                            }
+                        }
+
+                        match written {
+                            Ok(_) if unchanged => println!("Up to date!"),
+                            Ok(_) => match &signature {
+                                Some((_, key)) => println!("OK! Signed by key {}", key.get_id()),
+                                None => println!("OK!"),
+                            },
+                            Err(e) => {
+                                println!("Cannot write to repo! Error: {}", e);
+                                return;
+                            }
+                        }
+
+                        if let Some((_, key)) = signature {
+                            self.rotate_keys(repo, &key, content.get_body());
+                        }
+
+                        state.set_validators(content.get_etag(), content.get_last_modified());
+                        state.set_sha256(sha256);
+                        repo_states.insert(state);
                     }
+                    Err(e) => println!("Error while getting remote repo! Error: {}", e),
                 }
             }
-            Err(e) => {
-                println!("Error: {}! Exiting plugin manager!", e);
-                exit(1);
-            }
+            None => println!("Skipping! No remote defined in repo!"),
         }
-        remote_url
     }
 
     pub fn list(&self) -> String {
@@ -570,9 +2553,1278 @@ impl PluginManager {
 
         for plugin in &self.plugins {
             return_string.push_str(plugin.0);
-            return_string.push_str("\n");
+            return_string.push('\n');
+        }
+
+        for collection in &self.collections {
+            return_string.push_str(&format!("{} (collection)\n", collection.0));
+        }
+
+        return_string
+    }
+
+    pub fn info(&self, args: std::env::Args) {
+        let plugins_to_show: Vec<String> = args.skip(2).collect();
+        for plugin in plugins_to_show {
+            match self.plugins.get(&plugin) {
+                Some(selected_plugin) => {
+                    println!("Name: {}", selected_plugin.get_name());
+                    println!("Type: {}", selected_plugin.get_plugin_type());
+                    if !selected_plugin.get_location().is_empty() {
+                        println!("Location: {}", selected_plugin.get_location());
+                    }
+                    if !selected_plugin.get_builds().is_empty() {
+                        println!("Builds:");
+                        for build in selected_plugin.get_builds() {
+                            if build.get_platform() == self.platform {
+                                println!(
+                                    "\t{} -> {} (this node)",
+                                    build.get_platform(),
+                                    build.get_location()
+                                );
+                            } else {
+                                println!("\t{} -> {}", build.get_platform(), build.get_location());
+                            }
+                        }
+                    }
+                    println!("Repo: {}", self.describe_repo(&selected_plugin.get_repo()));
+                    if let Some(git_ref) = selected_plugin.get_git_ref() {
+                        println!("Ref: {}", git_ref);
+                    }
+                    if !selected_plugin.get_packages().is_empty() {
+                        let database = PackageDatabase::load(&self.root_prefix);
+                        let packages: Vec<String> = selected_plugin
+                            .get_packages()
+                            .iter()
+                            .map(|x| {
+                                if database.is_installed(x) {
+                                    format!("{} (installed)", x)
+                                } else {
+                                    format!("{} (missing)", x)
+                                }
+                            })
+                            .collect();
+                        println!("System packages: {}", packages.join(", "));
+                    }
+                    if let Some(installed_plugin) = self.load_installed_cache().get(&plugin) {
+                        if let Some(git_ref) = installed_plugin.get_git_ref() {
+                            println!("Installed ref: {}", git_ref);
+                        }
+                        if let Some(sha256) = installed_plugin.get_sha256() {
+                            println!("Installed archive sha256: {}", sha256);
+                        }
+                        if let Some(commit) = installed_plugin.get_commit() {
+                            println!("Installed commit: {}", commit);
+                            for (path, commit) in installed_plugin.get_submodules() {
+                                println!("\tsubmodule {}: {}", path, commit);
+                            }
+                            if let Ok(modified) =
+                                self.vcs.status(&self.git_clone_path(selected_plugin), true)
+                            {
+                                if !modified.is_empty() {
+                                    println!("Modified files:");
+                                    for change in modified {
+                                        println!("\t{}", change);
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    if let Some(shadowed_plugins) = self.shadowed.get(&plugin) {
+                        println!("Shadowed entries:");
+                        for shadowed_plugin in shadowed_plugins {
+                            println!(
+                                "\t{} -> {}",
+                                self.describe_repo(&shadowed_plugin.get_repo()),
+                                shadowed_plugin.get_location()
+                            );
+                        }
+                    }
+                    println!();
+                }
+                None => match self.collections.get(&plugin) {
+                    Some(collection) => {
+                        println!("Name: {}", collection.get_name());
+                        println!("Type: {}", PluginType::Collection);
+                        println!("Location: {}", collection.get_location());
+                        println!("Repo: {}", self.describe_repo(&collection.get_repo()));
+                        println!("Members:");
+                        for member in collection.get_members() {
+                            let (_, member_name) = split_qualified_name(&member);
+                            if self.is_installed(member_name) {
+                                println!("\t{} (installed)", member);
+                            } else {
+                                println!("\t{}", member);
+                            }
+                        }
+                        println!();
+                    }
+                    None => match self
+                        .load_installed_cache()
+                        .get(&plugin)
+                        .and_then(|x| x.get_origin())
+                    {
+                        Some((plugin_type, origin)) => {
+                            println!("Name: {}", plugin);
+                            println!("Type: {}", plugin_type);
+                            println!("Location: {}", origin);
+                            if self
+                                .load_installed_cache()
+                                .get(&plugin)
+                                .is_some_and(|x| x.is_linked())
+                            {
+                                println!("Linked: yes");
+                            }
+                            println!("Repo: {}", AD_HOC_REPO);
+                            println!();
+                        }
+                        None => println!("No plugin named {}!", plugin),
+                    },
+                },
+            }
+        }
+    }
+
+    /// Lists the system packages needed by the installed plugins, with the
+    /// plugins needing each.
+    pub fn list_packages(&self) -> String {
+        let database = PackageDatabase::load(&self.root_prefix);
+        let mut packages: Vec<(String, Vec<String>)> = Vec::new();
+        for installed_plugin in self.get_installed_plugins() {
+            for package in installed_plugin.get_packages() {
+                match packages.iter_mut().find(|(x, _)| *x == package) {
+                    Some((_, needed_by)) => needed_by.push(installed_plugin.get_name()),
+                    None => packages.push((package, vec![installed_plugin.get_name()])),
+                }
+            }
+        }
+        packages.sort();
+
+        let mut return_string = String::new();
+        for (package, needed_by) in packages {
+            let state = if database.is_installed(&package) {
+                "installed"
+            } else {
+                "missing"
+            };
+            return_string.push_str(&format!(
+                "{} ({}): {}\n",
+                package,
+                state,
+                needed_by.join(", ")
+            ));
+        }
+
+        return_string
+    }
+
+    fn describe_repo(&self, name: &str) -> String {
+        match self.get_repo(name) {
+            Some(repo) => format!("{} (priority {})", repo.get_name(), repo.get_priority()),
+            None => name.to_string(),
+        }
+    }
+
+    pub fn list_repos(&self) -> String {
+        let mut return_string = String::new();
+
+        for repo in &self.repos {
+            return_string.push_str(&repo.to_string());
+            return_string.push('\n');
+        }
+
+        return_string
+    }
+
+    pub fn add_key(&self, args: std::env::Args) {
+        let args: Vec<String> = args.skip(3).collect();
+        let mut location = None;
+        let mut repos = Vec::new();
+        let mut expires = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--repos" => {
+                    repos = args
+                        .next()
+                        .map(|x| x.split(',').map(|x| x.trim().to_string()).collect())
+                        .unwrap_or_default()
+                }
+                "--expires" => match args.next().map(|x| parse_date(x)) {
+                    Some(Ok(date)) => expires = Some(date),
+                    Some(Err(e)) => {
+                        println!("{}", e);
+                        return;
+                    }
+                    None => {
+                        println!("Missing date after --expires!");
+                        return;
+                    }
+                },
+                _ => location = Some(arg.clone()),
+            }
+        }
+
+        let location = match location {
+            Some(location) => location,
+            None => {
+                println!("No key file specified!");
+                return;
+            }
+        };
+
+        match trust::read_public_key_file(&location)
+            .and_then(|public_key| TrustedKey::new(public_key, repos, expires))
+        {
+            Ok(key) => {
+                let mut trust_store = self.load_trust_store();
+                println!("Adding key {}...", key.get_id());
+                trust_store.insert(key);
+                match trust_store.save() {
+                    Ok(_) => println!("OK!"),
+                    Err(e) => println!("Cannot write trust store! Error: {}", e),
+                }
+            }
+            Err(e) => println!("Cannot read key from {}! Error: {}", location, e),
+        }
+    }
+
+    pub fn list_keys(&self) -> String {
+        let mut return_string = String::new();
+
+        for key in self.load_trust_store().get_keys() {
+            return_string.push_str(&key.to_string());
+            return_string.push('\n');
         }
 
         return_string
     }
+
+    pub fn remove_key(&self, args: std::env::Args) {
+        let mut trust_store = self.load_trust_store();
+
+        let mut removed = false;
+        let mut missing = false;
+        for id in args.skip(3) {
+            if trust_store.remove(&id) {
+                println!("Removed key {}!", id);
+                removed = true;
+            } else {
+                println!("Error! No key with id {}!", id);
+                missing = true;
+            }
+        }
+
+        if removed {
+            if let Err(e) = trust_store.save() {
+                println!("Cannot write trust store! Error: {}", e);
+                exit(1);
+            }
+        }
+        if missing || !removed {
+            exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Reply, TestServer};
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::{Arc, Mutex};
+    use tempfile::TempDir;
+
+    /// Plugin manager with every location inside `root`, repos are not
+    /// signature checked.
+    fn manager(root: &Path) -> PluginManager {
+        let location = |name: &str| root.join(name).display().to_string();
+        for folder in ["repos", "plugins", "collections", "sources"] {
+            fs::create_dir_all(root.join(folder)).unwrap();
+        }
+        fs::write(root.join("plugins.repo"), "").unwrap();
+        fs::write(root.join(".installed"), "").unwrap();
+
+        PluginManager {
+            plugins: HashMap::new(),
+            shadowed: HashMap::new(),
+            collections: HashMap::new(),
+            repos: Vec::new(),
+            config_location: location("config.conf"),
+            official_repo_location: location("plugins.repo"),
+            repo_folder_location: location("repos"),
+            installed_cache_location: location(".installed"),
+            plugin_folder_location: location("plugins"),
+            collection_cache_location: location("collections"),
+            repo_state_location: location(".repo_state"),
+            trust_store_location: location("trusted.keys"),
+            source_folder_location: location("sources"),
+            backup_folder_location: location("backups"),
+            credentials_location: location("credentials"),
+            log_folder_location: location("logs"),
+            untrusted_repos: ["official", "a", "b", "team"]
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            git_backend: String::from("builtin"),
+            credentials: Credentials::default(),
+            vcs: Box::new(BuiltinGit::new(Credentials::default())),
+            dirty_policy: DirtyPolicy::Abort,
+            block_untracked: false,
+            platform: String::from("linux-aarch64"),
+            root_prefix: String::new(),
+            requirement_policy: RequirementPolicy::Refuse,
+            package_command: None,
+        }
+    }
+
+    fn plugin_entry(name: &str, location: &str) -> String {
+        format!(
+            "[{}]\nenabled=true\ntype=local\nlocation={}\n\n",
+            name, location
+        )
+    }
+
+    #[test]
+    fn duplicate_repo_names_are_rejected() {
+        let root = TempDir::new().unwrap();
+        let mut plugin_manager = manager(root.path());
+        let repos = root.path().join("repos");
+        fs::write(repos.join("team.list"), plugin_entry("web", "/opt/list")).unwrap();
+        fs::write(repos.join("team.repo"), plugin_entry("web", "/opt/repo")).unwrap();
+        fs::write(repos.join("official.repo"), plugin_entry("web", "/opt/x")).unwrap();
+
+        plugin_manager.cache_repos();
+
+        let names: Vec<String> = plugin_manager.repos.iter().map(|x| x.get_name()).collect();
+        assert_eq!(names, [OFFICIAL_REPO_NAME, "team"]);
+        let team = plugin_manager.get_repo("team").unwrap();
+        assert!(team.get_location().ends_with("team.list"));
+    }
+
+    #[test]
+    fn plain_names_resolve_to_the_repo_with_the_highest_precedence() {
+        let root = TempDir::new().unwrap();
+        let mut plugin_manager = manager(root.path());
+        let repos = root.path().join("repos");
+        fs::write(
+            root.path().join("plugins.repo"),
+            plugin_entry("web", "/opt/official"),
+        )
+        .unwrap();
+        fs::write(repos.join("a.repo"), plugin_entry("web", "/opt/a")).unwrap();
+        fs::write(
+            repos.join("b.repo"),
+            format!("priority=5\n\n{}", plugin_entry("web", "/opt/b")),
+        )
+        .unwrap();
+
+        plugin_manager.cache_repos();
+
+        let location = |name: &str| {
+            plugin_manager
+                .resolve_plugin(name)
+                .map(|x| x.get_location())
+        };
+        assert_eq!(location("web").as_deref(), Some("/opt/b"));
+        assert_eq!(location("a/web").as_deref(), Some("/opt/a"));
+        assert_eq!(location("official/web").as_deref(), Some("/opt/official"));
+        assert_eq!(location("missing/web"), None);
+        assert_eq!(plugin_manager.shadowed.get("web").map(|x| x.len()), Some(2));
+    }
+
+    #[test]
+    fn uninstall_refuses_path_components_and_unrecorded_plugins() {
+        let root = TempDir::new().unwrap();
+        let plugin_manager = manager(root.path());
+        let plugins = root.path().join("plugins");
+        fs::create_dir(plugins.join("web")).unwrap();
+
+        for name in ["..", ".", "", "web/..", "/"] {
+            plugin_manager.uninstall_plugin(name);
+            assert!(plugin_manager.remove_plugin_files(name).is_err());
+        }
+        plugin_manager.uninstall_plugin("web");
+
+        assert!(plugins.join("web").is_dir());
+        assert!(root.path().join("sources").is_dir());
+        assert!(root.path().join("plugins.repo").is_file());
+    }
+
+    #[test]
+    fn uninstall_removes_recorded_plugins() {
+        let root = TempDir::new().unwrap();
+        let plugin_manager = manager(root.path());
+        let plugin = Plugin::new(
+            String::from("web"),
+            true,
+            PluginType::Local,
+            String::new(),
+            String::from("a"),
+        );
+        fs::create_dir(root.path().join("plugins/web")).unwrap();
+        plugin_manager.add_to_installed_cache(&plugin, None);
+
+        plugin_manager.uninstall_plugin("web");
+
+        assert!(!root.path().join("plugins/web").exists());
+        assert!(plugin_manager.load_installed_cache().get("web").is_none());
+    }
+
+    fn installed(name: &str, collections: &[&str], auto: bool) -> InstalledPlugin {
+        let mut installed_plugin = InstalledPlugin::new(name.to_string(), Some(String::from("a")));
+        installed_plugin.set_collections(collections.iter().map(|x| x.to_string()).collect());
+        installed_plugin.set_auto(auto);
+        installed_plugin
+    }
+
+    #[test]
+    fn uninstalling_a_collection_keeps_members_still_needed() {
+        let root = TempDir::new().unwrap();
+        let plugin_manager = manager(root.path());
+        let mut installed_cache = plugin_manager.load_installed_cache();
+        for (name, collections, auto) in [
+            ("only", vec!["stack"], true),
+            ("shared", vec!["stack", "other"], true),
+            ("named", vec!["stack"], false),
+            ("unrelated", vec![], false),
+        ] {
+            fs::create_dir(root.path().join("plugins").join(name)).unwrap();
+            installed_cache.insert(installed(name, &collections, auto));
+        }
+        installed_cache.save().unwrap();
+
+        let members: Vec<InstalledPlugin> = installed_cache
+            .get_plugins()
+            .iter()
+            .filter(|x| x.get_collections().contains(&String::from("stack")))
+            .cloned()
+            .collect();
+        for member in &members {
+            plugin_manager.uninstall_collection_member(member, "stack");
+        }
+
+        let installed_cache = plugin_manager.load_installed_cache();
+        assert!(installed_cache.get("only").is_none());
+        assert!(!root.path().join("plugins/only").exists());
+        assert_eq!(
+            installed_cache.get("shared").unwrap().get_collections(),
+            ["other"]
+        );
+        assert!(installed_cache
+            .get("named")
+            .unwrap()
+            .get_collections()
+            .is_empty());
+        assert!(installed_cache.get("unrelated").is_some());
+        for name in ["shared", "named", "unrelated"] {
+            assert!(root.path().join("plugins").join(name).is_dir());
+        }
+    }
+
+    #[test]
+    fn reinstalling_keeps_collection_membership() {
+        let root = TempDir::new().unwrap();
+        let plugin_manager = manager(root.path());
+        plugin_manager.save_installed_plugin(installed("web", &["stack"], true));
+
+        plugin_manager.save_installed_plugin(InstalledPlugin::new(String::from("web"), None));
+        let installed_cache = plugin_manager.load_installed_cache();
+        let installed_plugin = installed_cache.get("web").unwrap();
+        assert_eq!(installed_plugin.get_collections(), ["stack"]);
+        assert!(installed_plugin.is_auto());
+
+        plugin_manager.record_install_reason("web", None, true);
+        assert!(!plugin_manager
+            .load_installed_cache()
+            .get("web")
+            .unwrap()
+            .is_auto());
+    }
+
+    #[test]
+    fn disabled_collections_are_not_loaded() {
+        let root = TempDir::new().unwrap();
+        let mut plugin_manager = manager(root.path());
+        let collection = root.path().join("stack.repo");
+        fs::write(&collection, plugin_entry("web", "/opt/web")).unwrap();
+        fs::write(
+            root.path().join("repos/a.repo"),
+            format!(
+                "[stack]\nenabled=false\ntype=collection\nlocation={}\n\n[tools]\nenabled=true\ntype=collection\nlocation={}\n",
+                collection.display(),
+                collection.display()
+            ),
+        )
+        .unwrap();
+
+        plugin_manager.cache_repos();
+
+        assert!(!plugin_manager.collections.contains_key("stack"));
+        assert_eq!(plugin_manager.collections["tools"].get_members(), ["a/web"]);
+    }
+
+    #[test]
+    fn disabled_collections_are_not_fetched() {
+        let root = TempDir::new().unwrap();
+        let server = TestServer::start(|_| Reply::ok(&plugin_entry("web", "/opt/web")));
+        let mut plugin_manager = manager(root.path());
+        fs::write(
+            root.path().join("repos/a.repo"),
+            format!(
+                "[stack]\nenabled=false\ntype=collection\nlocation={}\n\n\
+                 [tools]\nenabled=true\ntype=collection\nlocation={}\n",
+                server.url("/stack.repo"),
+                server.url("/tools.repo")
+            ),
+        )
+        .unwrap();
+        plugin_manager.cache_repos();
+
+        plugin_manager.update();
+
+        assert_eq!(server.get_paths(), ["/tools.repo"]);
+        let stack = plugin_manager.collection_cache_path(&server.url("/stack.repo"));
+        assert!(!Path::new(&stack).exists());
+    }
+
+    #[test]
+    fn collection_cycles_are_detected() {
+        let root = TempDir::new().unwrap();
+        let mut plugin_manager = manager(root.path());
+        let itself = root.path().join("self.repo");
+        let one = root.path().join("one.repo");
+        let two = root.path().join("two.repo");
+        let collection = |name: &str, location: &Path| {
+            format!(
+                "[{}]\nenabled=true\ntype=collection\nlocation={}\n\n",
+                name,
+                location.display()
+            )
+        };
+        fs::write(
+            &itself,
+            plugin_entry("web", "/opt/web") + &collection("again", &itself),
+        )
+        .unwrap();
+        fs::write(&one, plugin_entry("x", "/opt/x") + &collection("two", &two)).unwrap();
+        fs::write(&two, plugin_entry("y", "/opt/y") + &collection("one", &one)).unwrap();
+        fs::write(
+            root.path().join("repos/a.repo"),
+            collection("stack", &itself),
+        )
+        .unwrap();
+        fs::write(root.path().join("repos/b.repo"), collection("loop", &one)).unwrap();
+
+        plugin_manager.cache_repos();
+
+        assert_eq!(plugin_manager.collections["stack"].get_members(), ["a/web"]);
+        assert!(!plugin_manager.collections.contains_key("again"));
+        assert_eq!(
+            plugin_manager.collections["loop"].get_members(),
+            ["b/x", "b/y"]
+        );
+        assert_eq!(plugin_manager.collections["two"].get_members(), ["b/y"]);
+        assert!(!plugin_manager.collections.contains_key("one"));
+
+        let mut include_chain = Vec::new();
+        for location in [&one, &two] {
+            plugin_manager
+                .enter_collection(&location.display().to_string(), &mut include_chain)
+                .unwrap();
+        }
+        let error = plugin_manager
+            .enter_collection(&one.display().to_string(), &mut include_chain)
+            .unwrap_err();
+        assert!(error.starts_with("Collection cycle detected: "));
+        assert!(error.ends_with(&format!("two.repo -> {}", one.display())));
+    }
+
+    #[test]
+    fn remote_collections_are_fetched_on_update() {
+        let root = TempDir::new().unwrap();
+        let base = Arc::new(Mutex::new(String::new()));
+        let server_base = base.clone();
+        let server = TestServer::start(move |request| {
+            let collection = |name: &str| {
+                format!(
+                    "[{}]\nenabled=true\ntype=collection\nlocation={}/{}.repo\n\n",
+                    name,
+                    server_base.lock().unwrap(),
+                    name
+                )
+            };
+            match request.get_path() {
+                "/one.repo" => Reply::ok(&(plugin_entry("web", "/opt/web") + &collection("two"))),
+                _ => Reply::ok(&(plugin_entry("db", "/opt/db") + &collection("one"))),
+            }
+        });
+        *base.lock().unwrap() = server.url("");
+        let mut plugin_manager = manager(root.path());
+        fs::write(
+            root.path().join("repos/a.repo"),
+            format!(
+                "[stack]\nenabled=true\ntype=collection\nlocation={}\n",
+                server.url("/one.repo")
+            ),
+        )
+        .unwrap();
+        plugin_manager.cache_repos();
+        assert!(!plugin_manager.collections.contains_key("stack"));
+
+        plugin_manager.update();
+
+        assert_eq!(server.get_paths(), ["/one.repo", "/two.repo"]);
+        let states = RepoStateCache::load(&plugin_manager.repo_state_location);
+        for path in ["/one.repo", "/two.repo"] {
+            let url = server.url(path);
+            let state = states.get(&format!(
+                "{}{}",
+                COLLECTION_STATE_PREFIX,
+                remote::cache_file_name(&url)
+            ));
+            let content = fs::read(plugin_manager.collection_cache_path(&url)).unwrap();
+            assert_eq!(state.get_sha256(), Some(sha256_hex(&content)));
+        }
+
+        plugin_manager.cache_repos();
+
+        assert_eq!(
+            plugin_manager.collections["stack"].get_members(),
+            ["a/web", "a/db"]
+        );
+        assert_eq!(plugin_manager.collections["two"].get_members(), ["a/db"]);
+    }
+
+    #[test]
+    fn unchanged_repos_keep_their_local_copy_and_state() {
+        let root = TempDir::new().unwrap();
+        let base = Arc::new(Mutex::new(String::new()));
+        let server_base = base.clone();
+        let body = move || {
+            format!("remote={}/a.repo\n\n", server_base.lock().unwrap())
+                + &plugin_entry("web", "/opt/web")
+        };
+        let server_body = body.clone();
+        let server = TestServer::start(move |request| {
+            if request.get_header("if-none-match").is_some() {
+                Reply::status(304)
+            } else {
+                Reply::ok(&server_body())
+                    .header("ETag", "\"v1\"")
+                    .header("Last-Modified", "Mon, 19 Oct 2026 08:00:00 GMT")
+            }
+        });
+        *base.lock().unwrap() = server.url("");
+        let mut plugin_manager = manager(root.path());
+        let local_copy = root.path().join("repos/a.repo");
+        fs::write(&local_copy, body()).unwrap();
+        let modified = || fs::metadata(&local_copy).unwrap().modified().unwrap();
+        let written = modified();
+        plugin_manager.cache_repos();
+
+        // Same content, only the validators are recorded.
+        plugin_manager.update();
+        assert_eq!(modified(), written);
+        let state = fs::read_to_string(&plugin_manager.repo_state_location).unwrap();
+        assert!(state.contains("etag=\"v1\""));
+        assert!(state.contains(&format!("sha256={}", sha256_hex(body().as_bytes()))));
+
+        // Not modified, nothing is written.
+        plugin_manager.update();
+        assert_eq!(modified(), written);
+        assert_eq!(
+            fs::read_to_string(&plugin_manager.repo_state_location).unwrap(),
+            state
+        );
+        let requests = server.get_requests();
+        assert_eq!(requests[1].get_header("if-none-match"), Some("\"v1\""));
+        assert_eq!(
+            requests[1].get_header("if-modified-since"),
+            Some("Mon, 19 Oct 2026 08:00:00 GMT")
+        );
+
+        // A changed local copy drops the validators and is fetched again.
+        fs::write(&local_copy, body() + "[db]\nenabled=false\n").unwrap();
+        plugin_manager.update();
+        assert_eq!(server.get_requests()[2].get_header("if-none-match"), None);
+        assert_eq!(fs::read_to_string(&local_copy).unwrap(), body());
+    }
+
+    #[test]
+    fn local_repos_and_collections_are_validated() {
+        let root = TempDir::new().unwrap();
+        let mut plugin_manager = manager(root.path());
+        let collection = root.path().join("stack.repo");
+        fs::write(&collection, plugin_entry("..", "/opt/up")).unwrap();
+        fs::write(
+            root.path().join("repos/a.repo"),
+            format!(
+                "[stack]\nenabled=true\ntype=collection\nlocation={}\n\n{}",
+                collection.display(),
+                plugin_entry("web", "/opt/web")
+            ),
+        )
+        .unwrap();
+        fs::write(
+            root.path().join("repos/b.repo"),
+            plugin_entry(".web", "/opt/x"),
+        )
+        .unwrap();
+
+        plugin_manager.cache_repos();
+
+        let mut names: Vec<&String> = plugin_manager.plugins.keys().collect();
+        names.sort();
+        assert_eq!(names, ["web"]);
+        assert!(plugin_manager.collections["stack"].get_members().is_empty());
+    }
+
+    const CURRENT_KEY: &str = "RWQBAgMEBQYHCAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+    const SUCCESSOR_KEY: &str = "RWQLDA0ODxAREgcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcH";
+
+    #[test]
+    fn rotation_only_hands_over_the_announcing_repo() {
+        let root = TempDir::new().unwrap();
+        let plugin_manager = manager(root.path());
+        let repo = |name: &str| {
+            Repo::new(
+                name.to_string(),
+                String::new(),
+                true,
+                0,
+                None,
+                Freshness::default(),
+            )
+        };
+        let announcement = format!("successor_key={}\n", SUCCESSOR_KEY);
+        for signing_repos in [vec![], vec!["a".to_string(), "b".to_string()]] {
+            let signing_key =
+                TrustedKey::new(CURRENT_KEY.to_string(), signing_repos, None).unwrap();
+            let _ = fs::remove_file(&plugin_manager.trust_store_location);
+
+            plugin_manager.rotate_keys(&repo("a"), &signing_key, &announcement);
+            let trust_store = plugin_manager.load_trust_store();
+            let successor = &trust_store.get_keys()[0];
+            assert!(successor.is_allowed_for("a"));
+            assert!(!successor.is_allowed_for("b"));
+
+            plugin_manager.rotate_keys(&repo("b"), &signing_key, &announcement);
+            let trust_store = plugin_manager.load_trust_store();
+            assert_eq!(trust_store.get_keys().len(), 1);
+            let successor = &trust_store.get_keys()[0];
+            assert!(successor.is_allowed_for("a"));
+            assert!(successor.is_allowed_for("b"));
+            assert!(!successor.is_allowed_for("c"));
+        }
+    }
+
+    #[test]
+    fn manifest_requirements_are_checked_on_install() {
+        let root = TempDir::new().unwrap();
+        let mut plugin_manager = manager(root.path());
+        plugin_manager.root_prefix = root.path().display().to_string();
+        let source = root.path().join("web-source");
+        fs::create_dir(&source).unwrap();
+        fs::write(source.join("plugin.conf"), "boards=Raspberry Pi 4\n").unwrap();
+        fs::write(
+            root.path().join("plugins.repo"),
+            plugin_entry("web", &source.display().to_string()),
+        )
+        .unwrap();
+        plugin_manager.cache_repos();
+        let model = root.path().join("proc/device-tree/model");
+        fs::create_dir_all(model.parent().unwrap()).unwrap();
+
+        fs::write(&model, "Raspberry Pi 400 Rev 1.0\0").unwrap();
+        let plugin = plugin_manager.resolve_plugin("web").unwrap();
+        plugin_manager.install_plugin("web", plugin, None);
+        assert!(!root.path().join("plugins/web").exists());
+        assert!(!root.path().join("plugins/.web.new").exists());
+
+        fs::write(&model, "Raspberry Pi 4 Model B Rev 1.4\0").unwrap();
+        plugin_manager.install_plugin("web", plugin, None);
+        assert!(root.path().join("plugins/web/plugin.conf").is_file());
+    }
+
+    /// Package command appending the packages to the dpkg status database
+    /// below `root`, as if they were installed.
+    fn fake_package_command(root: &Path) -> String {
+        let status = root.join("var/lib/dpkg/status");
+        fs::create_dir_all(status.parent().unwrap()).unwrap();
+        fs::write(&status, "").unwrap();
+        let command = root.join("fake-apt");
+        fs::write(
+            &command,
+            format!(
+                "#!/bin/sh\nfor package in \"$@\"; do\n\
+                 printf 'Package: %s\\nStatus: install ok installed\\n\\n' \"$package\" >> {}\n\
+                 done\n",
+                status.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&command, fs::Permissions::from_mode(0o755)).unwrap();
+        command.display().to_string()
+    }
+
+    fn local_plugin_with_packages(root: &Path, name: &str, extra: &str) -> String {
+        let source = root.join(format!("{}-source", name));
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("index.html"), name).unwrap();
+        format!(
+            "{}{}\n\n",
+            plugin_entry(name, &source.display().to_string()).trim_end(),
+            extra
+        )
+    }
+
+    #[test]
+    fn only_installable_plugins_get_their_packages() {
+        let root = TempDir::new().unwrap();
+        let mut plugin_manager = manager(root.path());
+        plugin_manager.root_prefix = root.path().display().to_string();
+        plugin_manager.package_command = Some(fake_package_command(root.path()));
+        fs::write(
+            root.path().join("plugins.repo"),
+            local_plugin_with_packages(root.path(), "web", "\npackages=batctl")
+                + &local_plugin_with_packages(
+                    root.path(),
+                    "big",
+                    "\npackages=hostapd\nboards=Nonexistent Board",
+                ),
+        )
+        .unwrap();
+        plugin_manager.cache_repos();
+
+        plugin_manager.install_all(vec![String::from("web"), String::from("big")]);
+
+        let database = PackageDatabase::load(&plugin_manager.root_prefix);
+        assert!(database.is_installed("batctl"));
+        assert!(!database.is_installed("hostapd"));
+        assert!(root.path().join("plugins/web/index.html").is_file());
+        assert!(!root.path().join("plugins/big").exists());
+    }
+
+    #[test]
+    fn upgrades_install_and_record_new_packages() {
+        let root = TempDir::new().unwrap();
+        let mut plugin_manager = manager(root.path());
+        plugin_manager.root_prefix = root.path().display().to_string();
+        plugin_manager.package_command = Some(fake_package_command(root.path()));
+        let repo = root.path().join("plugins.repo");
+        fs::write(&repo, local_plugin_with_packages(root.path(), "web", "")).unwrap();
+        plugin_manager.cache_repos();
+        plugin_manager.install_all(vec![String::from("web")]);
+        assert_eq!(plugin_manager.list_packages(), "");
+
+        // A fresh manager starts with empty repo and installed files.
+        let installed = fs::read_to_string(root.path().join(".installed")).unwrap();
+        let package_command = plugin_manager.package_command.clone();
+        let mut plugin_manager = manager(root.path());
+        plugin_manager.root_prefix = root.path().display().to_string();
+        plugin_manager.package_command = package_command;
+        fs::write(root.path().join(".installed"), installed).unwrap();
+        fs::write(
+            &repo,
+            local_plugin_with_packages(root.path(), "web", "\npackages=batctl"),
+        )
+        .unwrap();
+        plugin_manager.cache_repos();
+        plugin_manager.upgrade_all(vec![String::from("web")]);
+
+        assert!(PackageDatabase::load(&plugin_manager.root_prefix).is_installed("batctl"));
+        assert_eq!(
+            plugin_manager
+                .load_installed_cache()
+                .get("web")
+                .unwrap()
+                .get_packages(),
+            ["batctl"]
+        );
+        assert!(plugin_manager
+            .list_packages()
+            .starts_with("batctl (installed): web"));
+    }
+
+    #[test]
+    fn ad_hoc_plugins_get_the_packages_of_their_manifest() {
+        let root = TempDir::new().unwrap();
+        let mut plugin_manager = manager(root.path());
+        plugin_manager.root_prefix = root.path().display().to_string();
+        plugin_manager.package_command = Some(fake_package_command(root.path()));
+        let source = root.path().join("source");
+        fs::create_dir(&source).unwrap();
+        fs::write(source.join("plugin.conf"), "name=web\npackages=batctl\n").unwrap();
+        let invalid = root.path().join("invalid");
+        fs::create_dir(&invalid).unwrap();
+        fs::write(invalid.join("plugin.conf"), "name=bad\npackages=-oDebug\n").unwrap();
+
+        plugin_manager.install_all(vec![
+            source.display().to_string(),
+            invalid.display().to_string(),
+        ]);
+
+        assert!(PackageDatabase::load(&plugin_manager.root_prefix).is_installed("batctl"));
+        assert!(root.path().join("plugins/web/plugin.conf").is_file());
+        assert_eq!(
+            plugin_manager
+                .load_installed_cache()
+                .get("web")
+                .unwrap()
+                .get_packages(),
+            ["batctl"]
+        );
+        assert_eq!(
+            plugin_manager
+                .resolve_installed_plugin("web")
+                .unwrap()
+                .get_packages(),
+            ["batctl"]
+        );
+        assert!(!root.path().join("plugins/bad").exists());
+    }
+
+    #[test]
+    fn plugins_with_failing_setup_scripts_stay_installed() {
+        let root = TempDir::new().unwrap();
+        let plugin_manager = manager(root.path());
+        let source = root.path().join("source");
+        fs::create_dir_all(source.join("setup_scripts")).unwrap();
+        fs::write(source.join("plugin.conf"), "name=web\n").unwrap();
+        let script = source.join("setup_scripts/setup.sh");
+        fs::write(&script, "#!/bin/sh\nexit 1\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        plugin_manager.install_all(vec![source.display().to_string()]);
+
+        assert!(root.path().join("plugins/web/plugin.conf").is_file());
+        assert!(plugin_manager.load_installed_cache().get("web").is_some());
+    }
+
+    #[test]
+    fn ad_hoc_plugins_are_upgraded_and_uninstalled_without_repos() {
+        let root = TempDir::new().unwrap();
+        let plugin_manager = manager(root.path());
+        let source = root.path().join("source");
+        fs::create_dir(&source).unwrap();
+        fs::write(source.join("plugin.conf"), "name=web\n").unwrap();
+        plugin_manager.install_all(vec![source.display().to_string()]);
+        fs::write(source.join("index.html"), "new").unwrap();
+
+        plugin_manager.upgrade_all(vec![String::from("web")]);
+
+        let plugin_path = root.path().join("plugins/web");
+        assert_eq!(
+            fs::read_to_string(plugin_path.join("index.html")).unwrap(),
+            "new"
+        );
+
+        plugin_manager.uninstall_all(vec![String::from("web")]);
+
+        assert!(!plugin_path.exists());
+        assert!(plugin_manager.load_installed_cache().get("web").is_none());
+        assert!(source.join("index.html").is_file());
+    }
+
+    #[test]
+    fn linked_plugins_point_to_their_folder() {
+        let root = TempDir::new().unwrap();
+        let plugin_manager = manager(root.path());
+        let source = root.path().join("source");
+        fs::create_dir(&source).unwrap();
+        fs::write(source.join("plugin.conf"), "name=web\n").unwrap();
+        let installed = root.path().join("installed");
+        fs::create_dir(&installed).unwrap();
+        fs::write(installed.join("plugin.conf"), "name=db\n").unwrap();
+        fs::create_dir(root.path().join("plugins/db")).unwrap();
+
+        plugin_manager.link_all(vec![
+            source.display().to_string(),
+            installed.display().to_string(),
+        ]);
+
+        let plugin_path = root.path().join("plugins/web");
+        assert_eq!(fs::read_link(&plugin_path).unwrap(), source);
+        let installed_cache = plugin_manager.load_installed_cache();
+        assert!(installed_cache.get("web").unwrap().is_linked());
+        assert!(root.path().join("plugins/db").is_dir());
+        assert!(!fs::symlink_metadata(root.path().join("plugins/db"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert!(installed_cache.get("db").is_none());
+
+        plugin_manager.unlink_all(vec![String::from("web"), String::from("db")]);
+
+        assert!(fs::symlink_metadata(&plugin_path).is_err());
+        assert!(source.join("plugin.conf").is_file());
+        assert!(plugin_manager.load_installed_cache().get("web").is_none());
+        assert!(root.path().join("plugins/db").is_dir());
+    }
+
+    /// Commits the files to the repository at `path`, creating it first.
+    fn commit_files(path: &Path, files: &[(&str, &str)]) -> String {
+        let repository = git2::Repository::open(path)
+            .or_else(|_| git2::Repository::init(path))
+            .unwrap();
+        for (name, content) in files {
+            fs::write(path.join(name), content).unwrap();
+        }
+        let mut index = repository.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("test", "test@example.org").unwrap();
+        let parent = repository.head().ok().and_then(|x| x.peel_to_commit().ok());
+        repository
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "commit",
+                &tree,
+                &parent.iter().collect::<Vec<_>>(),
+            )
+            .unwrap()
+            .to_string()
+    }
+
+    /// Adds the repository at `url` as a submodule at `path` and commits it.
+    fn add_submodule(repository_path: &Path, url: &Path, path: &str) -> String {
+        let repository = git2::Repository::open(repository_path).unwrap();
+        let mut submodule = repository
+            .submodule(&url.display().to_string(), Path::new(path), true)
+            .unwrap();
+        submodule.clone(None).unwrap();
+        submodule.add_finalize().unwrap();
+        commit_files(repository_path, &[])
+    }
+
+    #[test]
+    fn submodules_are_checked_out_recursively_and_recorded() {
+        let root = TempDir::new().unwrap();
+        let plugin_manager = manager(root.path());
+        let inner = root.path().join("inner");
+        let lib = root.path().join("lib");
+        let upstream = root.path().join("upstream");
+        for path in [&inner, &lib, &upstream] {
+            fs::create_dir(path).unwrap();
+        }
+        let inner_commit = commit_files(&inner, &[("inner.txt", "inner")]);
+        commit_files(&lib, &[("lib.txt", "lib")]);
+        let lib_commit = add_submodule(&lib, &inner, "inner");
+        commit_files(&upstream, &[("index.html", "1")]);
+        add_submodule(&upstream, &lib, "lib");
+        let plugin = Plugin::new(
+            String::from("web"),
+            true,
+            PluginType::Repo,
+            upstream.display().to_string(),
+            String::from("a"),
+        );
+
+        plugin_manager.install_plugin("web", &plugin, None);
+
+        let plugin_path = root.path().join("plugins/web");
+        assert!(plugin_path.join("lib/lib.txt").is_file());
+        assert!(plugin_path.join("lib/inner/inner.txt").is_file());
+        let submodules = vec![
+            (String::from("lib"), lib_commit),
+            (String::from("lib/inner"), inner_commit),
+        ];
+        assert_eq!(
+            plugin_manager
+                .load_installed_cache()
+                .get("web")
+                .unwrap()
+                .get_submodules(),
+            submodules
+        );
+        let installed_cache = fs::read_to_string(&plugin_manager.installed_cache_location).unwrap();
+        for (path, commit) in &submodules {
+            assert!(installed_cache.contains(&format!("submodule.{}={}", path, commit)));
+        }
+        let mut listed = vcs::GitCommand::new(Credentials::default())
+            .submodules(&plugin_path.display().to_string())
+            .unwrap();
+        listed.sort();
+        assert_eq!(listed, submodules);
+    }
+
+    #[test]
+    fn git_plugins_are_only_activated_once_built() {
+        let root = TempDir::new().unwrap();
+        let mut plugin_manager = manager(root.path());
+        let upstream = root.path().join("upstream");
+        fs::create_dir(&upstream).unwrap();
+        commit_files(
+            &upstream,
+            &[("plugin.conf", "build=exit 1\n"), ("index.html", "1")],
+        );
+        fs::write(
+            root.path().join("plugins.repo"),
+            format!(
+                "[web]\nenabled=true\ntype=repo\nlocation={}\n\n",
+                upstream.display()
+            ),
+        )
+        .unwrap();
+        plugin_manager.cache_repos();
+        let plugin = plugin_manager.resolve_plugin("web").unwrap().clone();
+        let plugin_path = root.path().join("plugins/web");
+
+        plugin_manager.install_plugin("web", &plugin, None);
+        assert!(!plugin_path.exists());
+        assert!(!root.path().join("plugins/.web.new").exists());
+
+        let first = commit_files(&upstream, &[("plugin.conf", "build=touch built\n")]);
+        plugin_manager.install_plugin("web", &plugin, None);
+        assert!(plugin_path.join("built").is_file());
+
+        commit_files(
+            &upstream,
+            &[("plugin.conf", "build=exit 1\n"), ("index.html", "2")],
+        );
+        assert!(!plugin_manager.upgrade_git_plugin(&plugin));
+        assert_eq!(fs::read(plugin_path.join("index.html")).unwrap(), b"1");
+        assert_eq!(
+            plugin_manager.vcs.head(&plugin_path.display().to_string()),
+            Ok(first)
+        );
+        assert!(!root.path().join("plugins/.web.new").exists());
+
+        commit_files(&upstream, &[("plugin.conf", "build=touch built\n")]);
+        assert!(plugin_manager.upgrade_git_plugin(&plugin));
+        assert_eq!(fs::read(plugin_path.join("index.html")).unwrap(), b"2");
+        assert!(plugin_path.join("built").is_file());
+    }
+
+    #[test]
+    fn subdir_plugins_are_staged_and_built_before_activation() {
+        let root = TempDir::new().unwrap();
+        let mut plugin_manager = manager(root.path());
+        let upstream = root.path().join("upstream");
+        fs::create_dir_all(upstream.join("web")).unwrap();
+        let first = commit_files(
+            &upstream,
+            &[
+                ("web/plugin.conf", "build=touch built\npreserve=data\n"),
+                ("web/index.html", "1"),
+            ],
+        );
+        fs::write(
+            root.path().join("plugins.repo"),
+            format!(
+                "[web]\nenabled=true\ntype=repo\nlocation={}\nsubdir=web\n\n",
+                upstream.display()
+            ),
+        )
+        .unwrap();
+        plugin_manager.cache_repos();
+        let plugin = plugin_manager.resolve_plugin("web").unwrap().clone();
+        let plugin_path = root.path().join("plugins/web");
+        let clone_path = root.path().join("sources/web").display().to_string();
+
+        plugin_manager.install_plugin("web", &plugin, None);
+        assert!(plugin_path.join("built").is_file());
+        fs::write(plugin_path.join("data"), "kept").unwrap();
+
+        commit_files(
+            &upstream,
+            &[
+                ("web/plugin.conf", "build=exit 1\n"),
+                ("web/index.html", "2"),
+            ],
+        );
+        assert!(!plugin_manager.upgrade_git_plugin(&plugin));
+        assert_eq!(fs::read(plugin_path.join("index.html")).unwrap(), b"1");
+        assert_eq!(plugin_manager.vcs.head(&clone_path), Ok(first));
+
+        let last = commit_files(
+            &upstream,
+            &[("web/plugin.conf", "build=touch built\npreserve=data\n")],
+        );
+        assert!(plugin_manager.upgrade_git_plugin(&plugin));
+        assert_eq!(fs::read(plugin_path.join("index.html")).unwrap(), b"2");
+        assert_eq!(fs::read(plugin_path.join("data")).unwrap(), b"kept");
+        assert_eq!(plugin_manager.vcs.head(&clone_path), Ok(last));
+        assert!(!root.path().join("sources/.web.new").exists());
+    }
+
+    #[test]
+    fn local_plugins_with_links_match_their_pinned_checksum() {
+        let root = TempDir::new().unwrap();
+        let mut plugin_manager = manager(root.path());
+        let source = root.path().join("web-source");
+        fs::create_dir(&source).unwrap();
+        fs::write(source.join("index.html"), "web").unwrap();
+        std::os::unix::fs::symlink("index.html", source.join("home.html")).unwrap();
+        let sha256 = checksum::sha256_dir(&source).unwrap();
+        fs::write(
+            root.path().join("plugins.repo"),
+            format!(
+                "{}\nsha256={}\n\n",
+                plugin_entry("web", &source.display().to_string()).trim_end(),
+                sha256
+            ),
+        )
+        .unwrap();
+        plugin_manager.cache_repos();
+        let plugin = plugin_manager.resolve_plugin("web").unwrap().clone();
+
+        plugin_manager.install_plugin("web", &plugin, None);
+
+        let link = root.path().join("plugins/web/home.html");
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("index.html"));
+        assert!(plugin_manager.upgrade_local_plugin(&plugin));
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("index.html"));
+    }
+
+    #[test]
+    fn pinned_commits_win_over_the_ref_of_the_entry() {
+        let root = TempDir::new().unwrap();
+        let mut plugin_manager = manager(root.path());
+        let upstream = root.path().join("upstream");
+        fs::create_dir(&upstream).unwrap();
+        let pinned = commit_files(&upstream, &[("index.html", "1")]);
+        let tip = commit_files(&upstream, &[("index.html", "2")]);
+        let branch = git2::Repository::open(&upstream)
+            .unwrap()
+            .head()
+            .unwrap()
+            .shorthand()
+            .unwrap()
+            .to_string();
+        fs::write(
+            root.path().join("plugins.repo"),
+            format!(
+                "[web]\nenabled=true\ntype=repo\nlocation={}\nref={}\ncommit={}\n\n",
+                upstream.display(),
+                branch,
+                pinned
+            ),
+        )
+        .unwrap();
+        plugin_manager.cache_repos();
+        let plugin = plugin_manager.resolve_plugin("web").unwrap().clone();
+        let plugin_path = root.path().join("plugins/web");
+        let head = |plugin_manager: &PluginManager| {
+            plugin_manager
+                .vcs
+                .head(&plugin_path.display().to_string())
+                .unwrap()
+        };
+
+        plugin_manager.install_plugin("web", &plugin, None);
+        assert_eq!(head(&plugin_manager), pinned);
+
+        fs::remove_dir_all(&plugin_path).unwrap();
+        plugin_manager.install_plugin("web", &plugin, Some(branch));
+        assert_eq!(head(&plugin_manager), tip);
+    }
 }
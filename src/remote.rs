@@ -0,0 +1,183 @@
+use reqwest::{
+    blocking::{RequestBuilder, Response},
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
+
+use crate::credentials::Credential;
+
+pub fn is_remote(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// Converts a remote location to a file name usable in a cache folder.
+pub fn cache_file_name(url: &str) -> String {
+    let name: String = url
+        .trim_end_matches('/')
+        .chars()
+        .map(|x| {
+            if x.is_ascii_alphanumeric() || x == '.' || x == '-' {
+                x
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!("{}.repo", name)
+}
+
+/// Adds the credentials of the host to a request, a token is sent as a
+/// bearer token.
+fn authorize(request: RequestBuilder, credential: Option<&Credential>) -> RequestBuilder {
+    let credential = match credential {
+        Some(credential) => credential,
+        None => return request,
+    };
+
+    match (credential.get_token(), credential.get_username()) {
+        (Some(token), _) => request.bearer_auth(token),
+        (None, Some(username)) => request.basic_auth(username, credential.get_password()),
+        (None, None) => request,
+    }
+}
+
+pub fn fetch(url: &str, credential: Option<&Credential>) -> Result<String, String> {
+    let response = authorize(reqwest::blocking::Client::new().get(url), credential)
+        .send()
+        .and_then(|x| x.error_for_status())
+        .map_err(|e| e.to_string())?;
+
+    response.text().map_err(|e| e.to_string())
+}
+
+/// Starts a download, the body is read from the returned response as it
+/// arrives. Large downloads take long, so there is no overall timeout.
+pub fn fetch_stream(url: &str, credential: Option<&Credential>) -> Result<Response, String> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(None)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    authorize(client.get(url), credential)
+        .send()
+        .and_then(|x| x.error_for_status())
+        .map_err(|e| e.to_string())
+}
+
+pub enum FetchResult {
+    NotModified,
+    Fetched(FetchedContent),
+}
+
+pub struct FetchedContent {
+    body: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl FetchedContent {
+    pub fn get_body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn get_etag(&self) -> Option<String> {
+        self.etag.clone()
+    }
+
+    pub fn get_last_modified(&self) -> Option<String> {
+        self.last_modified.clone()
+    }
+}
+
+/// Fetches the url with `If-None-Match`/`If-Modified-Since` set from the
+/// validators of the previous fetch.
+pub fn fetch_conditional(
+    url: &str,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    credential: Option<&Credential>,
+) -> Result<FetchResult, String> {
+    let mut request = authorize(reqwest::blocking::Client::new().get(url), credential);
+    if let Some(etag) = etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let response = request.send().map_err(|e| e.to_string())?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(FetchResult::NotModified);
+    }
+
+    let response = response.error_for_status().map_err(|e| e.to_string())?;
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|x: &reqwest::header::HeaderValue| x.to_str().ok())
+            .map(|x| x.to_string())
+    };
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+
+    Ok(FetchResult::Fetched(FetchedContent {
+        body: response.text().map_err(|e| e.to_string())?,
+        etag,
+        last_modified,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Reply, TestServer};
+
+    const LAST_MODIFIED_DATE: &str = "Mon, 19 Oct 2026 08:00:00 GMT";
+
+    #[test]
+    fn conditional_fetches_send_the_stored_validators() {
+        let server = TestServer::start(|request| {
+            if request.get_header("if-none-match") == Some("\"v1\"") {
+                Reply::status(304)
+            } else {
+                Reply::ok("[web]\n")
+                    .header("ETag", "\"v1\"")
+                    .header("Last-Modified", LAST_MODIFIED_DATE)
+            }
+        });
+        let url = server.url("/a.repo");
+
+        let content = match fetch_conditional(&url, None, None, None).unwrap() {
+            FetchResult::Fetched(content) => content,
+            FetchResult::NotModified => panic!("First fetch was not modified"),
+        };
+        assert_eq!(content.get_body(), "[web]\n");
+        assert_eq!(content.get_etag().as_deref(), Some("\"v1\""));
+        assert_eq!(
+            content.get_last_modified().as_deref(),
+            Some(LAST_MODIFIED_DATE)
+        );
+
+        let result =
+            fetch_conditional(&url, content.get_etag(), content.get_last_modified(), None).unwrap();
+        assert!(matches!(result, FetchResult::NotModified));
+
+        let requests = server.get_requests();
+        assert_eq!(requests[0].get_header("if-none-match"), None);
+        assert_eq!(requests[0].get_header("if-modified-since"), None);
+        assert_eq!(requests[1].get_header("if-none-match"), Some("\"v1\""));
+        assert_eq!(
+            requests[1].get_header("if-modified-since"),
+            Some(LAST_MODIFIED_DATE)
+        );
+    }
+
+    #[test]
+    fn failed_fetches_are_errors() {
+        let server = TestServer::start(|_| Reply::status(404));
+
+        assert!(fetch_conditional(&server.url("/a.repo"), None, None, None).is_err());
+    }
+}
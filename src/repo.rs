@@ -0,0 +1,505 @@
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display},
+    fs,
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    archive::{self, ArchiveFormat},
+    ini::{Document, Section},
+    manifest, packages,
+    plugin::{self, PluginType},
+    requirements,
+};
+
+pub const OFFICIAL_REPO_NAME: &str = "official";
+
+fn parse_timestamp(key: &str, timestamp: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|x| x.with_timezone(&Utc))
+        .map_err(|_| {
+            format!(
+                "Invalid {} '{}', expected an RFC 3339 timestamp",
+                key, timestamp
+            )
+        })
+}
+
+fn format_age(timestamp: DateTime<Utc>) -> String {
+    let age = Utc::now().signed_duration_since(timestamp);
+    let (amount, unit) = if age.num_days() > 0 {
+        (age.num_days(), "day")
+    } else if age.num_hours() > 0 {
+        (age.num_hours(), "hour")
+    } else {
+        (age.num_minutes().max(0), "minute")
+    };
+
+    if amount == 1 {
+        format!("{} {} old", amount, unit)
+    } else {
+        format!("{} {}s old", amount, unit)
+    }
+}
+
+/// When a repo was generated and until when it may be used, read from the
+/// `generated` and `valid-until` keys of the repo header. Both are covered by
+/// the signature, so an old index cannot be passed off as a new one.
+#[derive(Debug, Clone, Default)]
+pub struct Freshness {
+    generated: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+    stale: bool,
+}
+
+impl Freshness {
+    pub fn from_header(header: &Section) -> Result<Freshness, String> {
+        Ok(Freshness {
+            generated: match header.get("generated") {
+                Some(x) => Some(parse_timestamp("generated", &x)?),
+                None => None,
+            },
+            valid_until: match header.get("valid-until") {
+                Some(x) => Some(parse_timestamp("valid-until", &x)?),
+                None => None,
+            },
+            stale: false,
+        })
+    }
+
+    /// Freshness of a local copy. A copy with timestamps that cannot be read
+    /// is stale: it is still used, but replaced by the next fetched copy.
+    pub fn of_local_copy(content: &str) -> Freshness {
+        Freshness::from_header(Document::parse(content).get_header()).unwrap_or(Freshness {
+            stale: true,
+            ..Freshness::default()
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.valid_until.is_some_and(|x| x < Utc::now())
+    }
+
+    /// Whether the timestamps of the local copy could not be read.
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    pub fn get_valid_until(&self) -> Option<DateTime<Utc>> {
+        self.valid_until
+    }
+
+    /// Refuses a new copy generated before the current one, or without a
+    /// generation time when the current one has it.
+    pub fn check_newer(&self, new: &Freshness) -> Result<(), String> {
+        match (self.generated, new.generated) {
+            (Some(current), Some(new)) if new < current => Err(format!(
+                "Received repo was generated at {}, before the local copy generated at {}",
+                new, current
+            )),
+            (Some(_), None) => Err(String::from(
+                "Received repo has no generation time but the local copy has",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Platforms are written as `<os>-<arch>`, for example `linux-aarch64`.
+fn is_valid_platform(platform: &str) -> bool {
+    platform.split_once('-').is_some_and(|(os, arch)| {
+        !os.is_empty()
+            && !arch.is_empty()
+            && platform
+                .chars()
+                .all(|x| x.is_ascii_alphanumeric() || x == '-' || x == '_')
+    })
+}
+
+/// Keys a repo header may hold, anything else is not a repo.
+const HEADER_KEYS: [&str; 7] = [
+    "remote",
+    "enabled",
+    "priority",
+    "generated",
+    "valid-until",
+    "successor_key",
+    "current_key_expires",
+];
+
+/// Checks that fetched content is a usable repo before it replaces the local
+/// copy, so an error page or a captive portal cannot overwrite a working repo.
+pub fn validate(content: &str) -> Result<Document, String> {
+    if content.trim().is_empty() {
+        return Err(String::from("Repo is empty"));
+    }
+
+    let document = Document::parse_strict(content)?;
+    if let Some((key, _)) = document
+        .get_header()
+        .get_entries()
+        .iter()
+        .find(|(key, _)| !HEADER_KEYS.contains(&key.as_str()))
+    {
+        return Err(format!("Unknown key '{}' in the repo header", key));
+    }
+    if document.get_sections().is_empty() {
+        return Err(String::from("Repo has no plugins"));
+    }
+    validate_plugins(&document)?;
+    Freshness::from_header(document.get_header())?;
+
+    Ok(document)
+}
+
+/// Checks the plugin sections of a repo or collection. Local files are not
+/// fetched, they are checked with this when they are read.
+pub fn validate_plugins(document: &Document) -> Result<(), String> {
+    let mut names: Vec<String> = Vec::new();
+
+    for section in document.get_sections() {
+        let name = section.get_name();
+        if !manifest::is_valid_name(&name) {
+            return Err(format!("Invalid plugin name '{}'", name));
+        }
+        if names.contains(&name) {
+            return Err(format!("Plugin {} is defined more than once", name));
+        }
+
+        match section.get("type") {
+            Some(plugin_type) if PluginType::parse(&plugin_type).is_none() => {
+                return Err(format!("Unknown type '{}' for {}", plugin_type, name))
+            }
+            _ => {}
+        }
+        let mut locations: Vec<String> = section.get("location").into_iter().collect();
+        for (key, value) in section.get_entries() {
+            if let Some(platform) = key.strip_prefix(plugin::LOCATION_PREFIX) {
+                if !is_valid_platform(platform) {
+                    return Err(format!("Invalid platform '{}' for {}", platform, name));
+                }
+                locations.push(value.clone());
+            } else if let Some(platform) = key.strip_prefix(plugin::SHA256_PREFIX) {
+                if section
+                    .get(&format!("{}{}", plugin::LOCATION_PREFIX, platform))
+                    .is_none()
+                {
+                    return Err(format!("No location for sha256.{} of {}", platform, name));
+                }
+            }
+        }
+        if locations.is_empty() || locations.iter().any(|x| x.is_empty()) {
+            return Err(format!("Missing location for {}", name));
+        }
+        if section.get("type").as_deref() == Some("archive") {
+            for location in &locations {
+                if !archive::is_valid_location(location) {
+                    return Err(format!(
+                        "Archive of {} must be an http(s) or file url",
+                        name
+                    ));
+                }
+                if ArchiveFormat::from_location(location).is_none() {
+                    return Err(format!(
+                        "Archive of {} must be a .tar.gz, .tar.zst or .zip file",
+                        name
+                    ));
+                }
+            }
+        }
+        for (key, sha256) in section.get_entries() {
+            if key != "sha256" && !key.starts_with(plugin::SHA256_PREFIX) {
+                continue;
+            }
+            if sha256.len() != 64 || !sha256.chars().all(|x| x.is_ascii_hexdigit()) {
+                return Err(format!("Invalid {} for {}", key, name));
+            }
+        }
+        if let Some(git_ref) = section.get("ref") {
+            if !plugin::is_valid_ref(&git_ref) {
+                return Err(format!("Invalid ref for {}", name));
+            }
+        }
+        if let Some(subdir) = section.get("subdir") {
+            let subdir = subdir.trim_matches('/');
+            if subdir.is_empty()
+                || subdir.starts_with('-')
+                || subdir
+                    .split('/')
+                    .any(|x| x.is_empty() || x == "." || x == "..")
+            {
+                return Err(format!("Invalid subdir for {}", name));
+            }
+        }
+        if section
+            .get("submodules")
+            .is_some_and(|x| x != "true" && x != "false")
+        {
+            return Err(format!("Submodules of {} must be true or false", name));
+        }
+        for key in ["min_ram", "min_disk"] {
+            if section
+                .get(key)
+                .is_some_and(|x| requirements::parse_size(&x).is_none())
+            {
+                return Err(format!("Invalid {} for {}", key, name));
+            }
+        }
+        if section
+            .get("wifi_interfaces")
+            .is_some_and(|x| x.parse::<usize>().is_err())
+        {
+            return Err(format!("Invalid wifi_interfaces for {}", name));
+        }
+        if let Some(package) =
+            packages::split_packages(&section.get("packages").unwrap_or_default())
+                .iter()
+                .find(|x| !packages::is_valid_package(x))
+        {
+            return Err(format!("Invalid package '{}' for {}", package, name));
+        }
+        if let Some(commit) = section.get("commit") {
+            if commit.len() != 40 || !commit.chars().all(|x| x.is_ascii_hexdigit()) {
+                return Err(format!("Commit of {} must be a full commit hash", name));
+            }
+        }
+
+        names.push(name);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct Repo {
+    name: String,
+    location: String,
+    enabled: bool,
+    priority: i32,
+    remote: Option<String>,
+    freshness: Freshness,
+}
+
+impl Repo {
+    pub fn new(
+        name: String,
+        location: String,
+        enabled: bool,
+        priority: i32,
+        remote: Option<String>,
+        freshness: Freshness,
+    ) -> Repo {
+        Repo {
+            name,
+            location,
+            enabled,
+            priority,
+            remote,
+            freshness,
+        }
+    }
+
+    /// Reads the header of a repo file (the lines before the first plugin
+    /// section). A missing `enabled` key means enabled, a missing `priority`
+    /// means 0.
+    pub fn from_file(name: String, location: String) -> Result<Repo, String> {
+        let content = fs::read_to_string(&location).map_err(|e| e.to_string())?;
+        let document = Document::parse(&content);
+        let header = document.get_header();
+
+        let enabled = header.get("enabled").is_none_or(|x| x != "false");
+        let priority = match header.get("priority") {
+            Some(priority) => priority
+                .parse::<i32>()
+                .map_err(|_| format!("Invalid priority '{}' in repo {}", priority, name))?,
+            None => 0,
+        };
+
+        let freshness = Freshness::of_local_copy(&content);
+
+        Ok(Repo::new(
+            name,
+            location,
+            enabled,
+            priority,
+            header.get("remote"),
+            freshness,
+        ))
+    }
+
+    /// Derives the repo name from a file in the repo folder, `team.repo`
+    /// becomes `team`.
+    pub fn name_from_path(path: &Path) -> String {
+        path.file_stem()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_location(&self) -> String {
+        self.location.clone()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn get_priority(&self) -> i32 {
+        self.priority
+    }
+
+    pub fn get_remote(&self) -> Option<String> {
+        self.remote.clone()
+    }
+
+    pub fn get_freshness(&self) -> &Freshness {
+        &self.freshness
+    }
+
+    /// Precedence order of repos: higher priority first, on equal priority
+    /// the official repo comes first, the rest are ordered by name.
+    pub fn precedence(&self, other: &Repo) -> Ordering {
+        other
+            .priority
+            .cmp(&self.priority)
+            .then_with(|| {
+                (other.name == OFFICIAL_REPO_NAME).cmp(&(self.name == OFFICIAL_REPO_NAME))
+            })
+            .then_with(|| self.name.cmp(&other.name))
+    }
+}
+
+impl Display for Repo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let age = match self.freshness.generated {
+            _ if self.freshness.stale => String::from("invalid timestamps"),
+            Some(generated) => format_age(generated),
+            None => String::from("unknown age"),
+        };
+        let expiry = match self.freshness.valid_until {
+            Some(valid_until) if self.freshness.is_expired() => {
+                format!(", expired on {}", valid_until)
+            }
+            Some(valid_until) => format!(", valid until {}", valid_until),
+            None => String::new(),
+        };
+
+        write!(
+            f,
+            "{}\t{}\tpriority={}\t{}{}\t{}",
+            self.name,
+            if self.enabled { "enabled" } else { "disabled" },
+            self.priority,
+            age,
+            expiry,
+            self.remote
+                .clone()
+                .unwrap_or_else(|| String::from("(no remote)"))
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo(name: &str, priority: i32) -> Repo {
+        Repo::new(
+            name.to_string(),
+            String::new(),
+            true,
+            priority,
+            None,
+            Freshness::default(),
+        )
+    }
+
+    #[test]
+    fn precedence_orders_by_priority_then_official_then_name() {
+        let mut repos = [
+            repo("b", 0),
+            repo("a", 0),
+            repo(OFFICIAL_REPO_NAME, 0),
+            repo("low", -1),
+            repo("high", 10),
+        ];
+        repos.sort_by(|a, b| a.precedence(b));
+
+        let names: Vec<String> = repos.iter().map(|x| x.get_name()).collect();
+        assert_eq!(names, ["high", OFFICIAL_REPO_NAME, "a", "b", "low"]);
+    }
+
+    #[test]
+    fn higher_priority_beats_official() {
+        assert_eq!(
+            repo("team", 1).precedence(&repo(OFFICIAL_REPO_NAME, 0)),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn validate_rejects_names_unusable_as_folders() {
+        for name in ["..", ".", ".web", ".web.new", "a/b", "a b"] {
+            let content = format!("[{}]\nenabled=true\ntype=local\nlocation=/opt/x\n", name);
+            assert!(validate(&content).is_err(), "{}", name);
+        }
+        assert!(validate("[web]\nenabled=true\ntype=local\nlocation=/opt/x\n").is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_error_pages() {
+        for content in [
+            "<html><body onload=\"x\">",
+            "error=rate limited",
+            "generated=2026-10-19T10:00:00Z\n",
+        ] {
+            assert!(validate(content).is_err(), "{}", content);
+        }
+        assert!(validate(
+            "remote=https://example.org/x.repo\npriority=1\n\n\
+             [web]\nenabled=true\ntype=local\nlocation=/opt/x\n"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn shipped_repo_is_valid() {
+        let content = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/plugins.repo"));
+        assert!(validate(&content.unwrap()).is_ok());
+    }
+
+    #[test]
+    fn unreadable_timestamps_make_the_local_copy_stale() {
+        let root = tempfile::TempDir::new().unwrap();
+        let location = root.path().join("team.repo");
+        fs::write(
+            &location,
+            "generated=yesterday\n\n[web]\nenabled=true\ntype=local\nlocation=/opt/web\n",
+        )
+        .unwrap();
+
+        let repo = Repo::from_file(String::from("team"), location.display().to_string()).unwrap();
+
+        assert!(repo.get_freshness().is_stale());
+        let fetched = Freshness::of_local_copy("generated=2020-01-01T00:00:00Z\n");
+        assert!(!fetched.is_stale());
+        assert!(repo.get_freshness().check_newer(&fetched).is_ok());
+    }
+
+    #[test]
+    fn older_copies_are_refused() {
+        let local = Freshness::of_local_copy("generated=2026-10-19T10:00:00Z\n");
+        let older = Freshness::of_local_copy("generated=2026-10-18T10:00:00Z\n");
+        let newer = Freshness::of_local_copy("generated=2026-10-20T10:00:00Z\n");
+
+        assert!(local.check_newer(&older).is_err());
+        assert!(local.check_newer(&Freshness::default()).is_err());
+        assert!(local.check_newer(&newer).is_ok());
+    }
+}
@@ -0,0 +1,114 @@
+use std::fs;
+
+use crate::ini::{Document, Section};
+
+/// What the manager remembers about the last fetch of a repo or remote
+/// collection, used to send conditional requests on the next `update`.
+#[derive(Debug, Clone)]
+pub struct RepoState {
+    name: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    sha256: Option<String>,
+}
+
+impl RepoState {
+    pub fn new(name: String) -> RepoState {
+        RepoState {
+            name,
+            etag: None,
+            last_modified: None,
+            sha256: None,
+        }
+    }
+
+    fn from_section(section: &Section) -> RepoState {
+        RepoState {
+            name: section.get_name(),
+            etag: section.get("etag"),
+            last_modified: section.get("last_modified"),
+            sha256: section.get("sha256"),
+        }
+    }
+
+    fn to_section(&self) -> Section {
+        let mut section = Section::new(self.name.clone());
+        if let Some(etag) = &self.etag {
+            section.push("etag", etag.clone());
+        }
+        if let Some(last_modified) = &self.last_modified {
+            section.push("last_modified", last_modified.clone());
+        }
+        if let Some(sha256) = &self.sha256 {
+            section.push("sha256", sha256.clone());
+        }
+        section
+    }
+
+    pub fn get_etag(&self) -> Option<String> {
+        self.etag.clone()
+    }
+
+    pub fn get_last_modified(&self) -> Option<String> {
+        self.last_modified.clone()
+    }
+
+    pub fn get_sha256(&self) -> Option<String> {
+        self.sha256.clone()
+    }
+
+    pub fn set_validators(&mut self, etag: Option<String>, last_modified: Option<String>) {
+        self.etag = etag;
+        self.last_modified = last_modified;
+    }
+
+    pub fn set_sha256(&mut self, sha256: String) {
+        self.sha256 = Some(sha256);
+    }
+}
+
+pub struct RepoStateCache {
+    location: String,
+    states: Vec<RepoState>,
+}
+
+impl RepoStateCache {
+    /// Loads the state file, a missing file means no repo was fetched yet.
+    pub fn load(location: &str) -> RepoStateCache {
+        let states = match fs::read_to_string(location) {
+            Ok(content) => Document::parse(&content)
+                .get_sections()
+                .iter()
+                .map(RepoState::from_section)
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        RepoStateCache {
+            location: location.to_string(),
+            states,
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let mut document = Document::new();
+        for state in &self.states {
+            document.push_section(state.to_section());
+        }
+
+        fs::write(&self.location, document.to_string()).map_err(|e| e.to_string())
+    }
+
+    pub fn get(&self, name: &str) -> RepoState {
+        self.states
+            .iter()
+            .find(|x| x.name == name)
+            .cloned()
+            .unwrap_or_else(|| RepoState::new(name.to_string()))
+    }
+
+    pub fn insert(&mut self, state: RepoState) {
+        self.states.retain(|x| x.name != state.name);
+        self.states.push(state);
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use nix::sys::statvfs::statvfs;
+
+use crate::ini::Section;
+
+/// What install does with a plugin whose requirements are not met.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RequirementPolicy {
+    Refuse,
+    Warn,
+}
+
+impl RequirementPolicy {
+    pub fn parse(policy: &str) -> Option<RequirementPolicy> {
+        match policy {
+            "refuse" => Some(RequirementPolicy::Refuse),
+            "warn" => Some(RequirementPolicy::Warn),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a size like `512M` or `1G`, a number without unit is in bytes.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let (number, unit) = match size.find(|x: char| !x.is_ascii_digit()) {
+        Some(index) => size.split_at(index),
+        None => (size, ""),
+    };
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return None,
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+fn format_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+fn split_list(list: Option<String>) -> Vec<String> {
+    list.unwrap_or_default()
+        .split(',')
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+/// A board matches the model of the node if it is the whole model or its
+/// words up to a word boundary, so `Raspberry Pi 4` matches
+/// `Raspberry Pi 4 Model B Rev 1.4` but not `Raspberry Pi 400 Rev 1.0`.
+fn matches_board(model: &str, board: &str) -> bool {
+    model
+        .strip_prefix(board)
+        .is_some_and(|x| x.is_empty() || x.starts_with(' '))
+}
+
+/// Hardware and resources a plugin needs, from the `min_ram`, `min_disk`,
+/// `interfaces`, `wifi_interfaces`, `kernel_modules` and `boards` keys of its
+/// repo entry or its manifest.
+#[derive(Debug, Clone, Default)]
+pub struct Requirements {
+    min_ram: Option<u64>,
+    min_disk: Option<u64>,
+    interfaces: Vec<String>,
+    wifi_interfaces: Option<usize>,
+    kernel_modules: Vec<String>,
+    boards: Vec<String>,
+}
+
+impl Requirements {
+    pub fn from_section(section: &Section) -> Requirements {
+        Requirements {
+            min_ram: section.get("min_ram").and_then(|x| parse_size(&x)),
+            min_disk: section.get("min_disk").and_then(|x| parse_size(&x)),
+            interfaces: split_list(section.get("interfaces")),
+            wifi_interfaces: section.get("wifi_interfaces").and_then(|x| x.parse().ok()),
+            kernel_modules: split_list(section.get("kernel_modules")),
+            boards: split_list(section.get("boards")),
+        }
+    }
+
+    /// Checks the requirements against the system, returning every unmet one.
+    /// Free disk space is measured on the filesystem holding `disk_path`.
+    pub fn unmet(&self, system: &System, disk_path: &str) -> Vec<String> {
+        let mut unmet = Vec::new();
+
+        if let Some(min_ram) = self.min_ram {
+            match system.get_ram() {
+                Some(ram) if ram >= min_ram => {}
+                Some(ram) => unmet.push(format!(
+                    "needs {} of RAM, {} found",
+                    format_size(min_ram),
+                    format_size(ram)
+                )),
+                None => unmet.push(String::from("needs RAM, but its size cannot be read")),
+            }
+        }
+        if let Some(min_disk) = self.min_disk {
+            match system.get_free_disk(disk_path) {
+                Some(free) if free >= min_disk => {}
+                Some(free) => unmet.push(format!(
+                    "needs {} of free disk space, {} free",
+                    format_size(min_disk),
+                    format_size(free)
+                )),
+                None => unmet.push(String::from("needs free disk space, but it cannot be read")),
+            }
+        }
+
+        let interfaces = system.get_interfaces();
+        for interface in &self.interfaces {
+            if !interfaces.contains(interface) {
+                unmet.push(format!("needs network interface {}", interface));
+            }
+        }
+        if let Some(wifi_interfaces) = self.wifi_interfaces {
+            let found = interfaces.iter().filter(|x| system.is_wireless(x)).count();
+            if found < wifi_interfaces {
+                unmet.push(format!(
+                    "needs {} Wi-Fi interfaces, {} found",
+                    wifi_interfaces, found
+                ));
+            }
+        }
+
+        for module in &self.kernel_modules {
+            if !system.has_kernel_module(module) {
+                unmet.push(format!("needs kernel module {}", module));
+            }
+        }
+
+        if !self.boards.is_empty() {
+            match system.get_board_model() {
+                Some(model) if self.boards.iter().any(|x| matches_board(&model, x)) => {}
+                Some(model) => unmet.push(format!(
+                    "needs one of the boards {}, this is a {}",
+                    self.boards.join(", "),
+                    model
+                )),
+                None => unmet.push(format!(
+                    "needs one of the boards {}, but the board model cannot be read",
+                    self.boards.join(", ")
+                )),
+            }
+        }
+
+        unmet
+    }
+}
+
+/// Reads the state of the node from `/proc` and `/sys`. Every path is
+/// prefixed with the root prefix, so another root can be inspected.
+pub struct System {
+    root_prefix: PathBuf,
+}
+
+impl System {
+    pub fn new(root_prefix: &str) -> System {
+        System {
+            root_prefix: Path::new("/").join(root_prefix),
+        }
+    }
+
+    fn path(&self, path: &str) -> PathBuf {
+        self.root_prefix.join(path.trim_start_matches('/'))
+    }
+
+    fn read(&self, path: &str) -> Option<String> {
+        fs::read_to_string(self.path(path)).ok()
+    }
+
+    /// Total RAM as reported by `MemTotal`, which is below the nominal size
+    /// of the board as the kernel and the GPU keep a part.
+    pub fn get_ram(&self) -> Option<u64> {
+        self.read("/proc/meminfo")?
+            .lines()
+            .find_map(|x| x.strip_prefix("MemTotal:"))
+            .and_then(|x| parse_size(x.trim().trim_end_matches("kB")))
+            .map(|x| x * 1024)
+    }
+
+    pub fn get_free_disk(&self, path: &str) -> Option<u64> {
+        let stat = statvfs(path).ok()?;
+        Some(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+    }
+
+    pub fn get_interfaces(&self) -> Vec<String> {
+        fs::read_dir(self.path("/sys/class/net"))
+            .map(|x| {
+                x.filter_map(|x| x.ok())
+                    .map(|x| x.file_name().to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn is_wireless(&self, interface: &str) -> bool {
+        let interface_path = format!("/sys/class/net/{}", interface);
+        self.path(&interface_path).join("wireless").exists()
+            || self.path(&interface_path).join("phy80211").exists()
+    }
+
+    /// Whether a kernel module is loaded, built in, or can be loaded.
+    pub fn has_kernel_module(&self, module: &str) -> bool {
+        let module = module.replace('-', "_");
+        if self.path(&format!("/sys/module/{}", module)).is_dir() {
+            return true;
+        }
+
+        let release = match self.read("/proc/sys/kernel/osrelease") {
+            Some(release) => release.trim().to_string(),
+            None => return false,
+        };
+        ["modules.dep", "modules.builtin"].iter().any(|list| {
+            self.read(&format!("/lib/modules/{}/{}", release, list))
+                .unwrap_or_default()
+                .lines()
+                .filter_map(|x| x.split(':').next())
+                .filter_map(|x| Path::new(x).file_name())
+                .filter_map(|x| x.to_string_lossy().split(".ko").next().map(String::from))
+                .any(|x| x.replace('-', "_") == module)
+        })
+    }
+
+    /// Model of the board from the device tree, like
+    /// `Raspberry Pi 4 Model B Rev 1.4`.
+    pub fn get_board_model(&self) -> Option<String> {
+        self.read("/proc/device-tree/model")
+            .or_else(|| self.read("/sys/firmware/devicetree/base/model"))
+            .map(|x| x.trim_end_matches('\0').trim().to_string())
+            .filter(|x| !x.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ini::Document;
+
+    fn requirements(content: &str) -> Requirements {
+        Requirements::from_section(Document::parse(content).get_header())
+    }
+
+    /// Fixture root with the files the checks read.
+    fn system(files: &[(&str, &str)]) -> (tempfile::TempDir, System) {
+        let root = tempfile::tempdir().unwrap();
+        for (path, content) in files {
+            let path = root.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let system = System::new(&root.path().display().to_string());
+        (root, system)
+    }
+
+    #[test]
+    fn sizes_are_parsed() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("900M"), Some(900 << 20));
+        assert_eq!(parse_size("1 GiB"), Some(1 << 30));
+        assert_eq!(parse_size("1X"), None);
+        assert_eq!(parse_size("M"), None);
+    }
+
+    #[test]
+    fn requirements_are_read_below_the_root_prefix() {
+        let (root, system) = system(&[
+            (
+                "proc/meminfo",
+                "MemTotal:        3884096 kB\nMemFree: 1 kB\n",
+            ),
+            ("sys/class/net/eth0/address", ""),
+            ("sys/class/net/wlan0/wireless/x", ""),
+            ("sys/class/net/wlan1/phy80211/x", ""),
+            ("sys/module/batman_adv/refcnt", "0"),
+            ("proc/sys/kernel/osrelease", "6.6.31-v8+\n"),
+            (
+                "lib/modules/6.6.31-v8+/modules.dep",
+                "kernel/net/wireless/cfg80211.ko.xz: kernel/net/rfkill/rfkill.ko.xz\n",
+            ),
+            ("proc/device-tree/model", "Raspberry Pi 4 Model B Rev 1.4\0"),
+        ]);
+        let disk_path = root.path().display().to_string();
+
+        let met = requirements(
+            "min_ram=3G\nmin_disk=1\ninterfaces=eth0,wlan0\nwifi_interfaces=2\n\
+             kernel_modules=batman-adv,cfg80211\nboards=Raspberry Pi 5,Raspberry Pi 4\n",
+        );
+        assert_eq!(met.unmet(&system, &disk_path), Vec::<String>::new());
+
+        let unmet = requirements(
+            "min_ram=4G\ninterfaces=eth1\nwifi_interfaces=3\n\
+             kernel_modules=wireguard\nboards=Raspberry Pi 5\n",
+        );
+        assert_eq!(
+            unmet.unmet(&system, &disk_path),
+            [
+                "needs 4.0 GiB of RAM, 3.7 GiB found",
+                "needs network interface eth1",
+                "needs 3 Wi-Fi interfaces, 2 found",
+                "needs kernel module wireguard",
+                "needs one of the boards Raspberry Pi 5, this is a Raspberry Pi 4 Model B Rev 1.4",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_files_leave_requirements_unmet() {
+        let (root, system) = system(&[]);
+        let unmet = requirements("min_ram=1M\nkernel_modules=batman-adv\nboards=Raspberry Pi\n")
+            .unmet(&system, &root.path().display().to_string());
+        assert_eq!(unmet.len(), 3);
+    }
+
+    #[test]
+    fn boards_match_whole_words() {
+        let model = "Raspberry Pi 400 Rev 1.0";
+        assert!(matches_board(model, "Raspberry Pi 400"));
+        assert!(matches_board(model, "Raspberry Pi"));
+        assert!(matches_board(model, model));
+        assert!(!matches_board(model, "Raspberry Pi 4"));
+        assert!(!matches_board(model, "Raspberry P"));
+        assert!(matches_board(
+            "Raspberry Pi 4 Model B Rev 1.4",
+            "Raspberry Pi 4"
+        ));
+        assert!(matches_board(
+            "Raspberry Pi 4 Model B Rev 1.4",
+            "Raspberry Pi 4 Model B"
+        ));
+    }
+}
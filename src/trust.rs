@@ -0,0 +1,238 @@
+use std::{
+    fmt::{self, Display},
+    fs,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{Local, NaiveDate};
+use minisign_verify::{PublicKey, Signature};
+
+use crate::{
+    atomic,
+    ini::{Document, Section},
+};
+
+/// Extension of detached minisign signatures, both next to the remote url of a
+/// repo and for the local copy.
+pub const SIGNATURE_EXTENSION: &str = "minisig";
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+pub fn parse_date(date: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(date.trim(), DATE_FORMAT)
+        .map_err(|_| format!("Invalid date '{}', expected YYYY-MM-DD", date))
+}
+
+/// Returns the minisign key id of a base64 encoded public key, formatted the
+/// way `minisign` prints it.
+pub fn key_id(public_key: &str) -> Result<String, String> {
+    let bytes = STANDARD
+        .decode(public_key.trim())
+        .map_err(|_| String::from("Invalid public key encoding"))?;
+    if bytes.len() != 42 || PublicKey::from_base64(public_key.trim()).is_err() {
+        return Err(String::from("Invalid minisign public key"));
+    }
+
+    let mut id = [0u8; 8];
+    id.copy_from_slice(&bytes[2..10]);
+    Ok(format!("{:016X}", u64::from_le_bytes(id)))
+}
+
+/// Reads the public key out of a `minisign.pub` file, which has an untrusted
+/// comment line before the key, or out of a file holding only the key.
+pub fn read_public_key_file(location: &str) -> Result<String, String> {
+    let content = fs::read_to_string(location).map_err(|e| e.to_string())?;
+
+    content
+        .lines()
+        .map(|x| x.trim())
+        .find(|x| !x.is_empty() && !x.starts_with("untrusted comment:"))
+        .map(|x| x.to_string())
+        .ok_or_else(|| format!("No public key found in {}", location))
+}
+
+#[derive(Debug, Clone)]
+pub struct TrustedKey {
+    id: String,
+    public_key: String,
+    repos: Vec<String>,
+    expires: Option<NaiveDate>,
+}
+
+impl TrustedKey {
+    pub fn new(
+        public_key: String,
+        repos: Vec<String>,
+        expires: Option<NaiveDate>,
+    ) -> Result<TrustedKey, String> {
+        Ok(TrustedKey {
+            id: key_id(&public_key)?,
+            public_key: public_key.trim().to_string(),
+            repos,
+            expires,
+        })
+    }
+
+    fn from_section(section: &Section) -> TrustedKey {
+        TrustedKey {
+            id: section.get_name(),
+            public_key: section.get("public_key").unwrap_or_default(),
+            repos: section
+                .get("repos")
+                .unwrap_or_default()
+                .split(',')
+                .map(|x| x.trim().to_string())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            expires: section.get("expires").and_then(|x| parse_date(&x).ok()),
+        }
+    }
+
+    fn to_section(&self) -> Section {
+        let mut section = Section::new(self.id.clone());
+        section.push("public_key", self.public_key.clone());
+        if !self.repos.is_empty() {
+            section.push("repos", self.repos.join(","));
+        }
+        if let Some(expires) = self.expires {
+            section.push("expires", expires.format(DATE_FORMAT).to_string());
+        }
+        section
+    }
+
+    pub fn get_id(&self) -> String {
+        self.id.clone()
+    }
+
+    pub fn get_expires(&self) -> Option<NaiveDate> {
+        self.expires
+    }
+
+    pub fn set_expires(&mut self, expires: Option<NaiveDate>) {
+        self.expires = expires;
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|x| x < Local::now().date_naive())
+    }
+
+    /// Lets a key scoped to repos sign one more repo.
+    pub fn add_repo(&mut self, repo: String) {
+        if !self.is_allowed_for(&repo) {
+            self.repos.push(repo);
+        }
+    }
+
+    /// A key without repos may sign every repo.
+    pub fn is_allowed_for(&self, repo: &str) -> bool {
+        self.repos.is_empty() || self.repos.iter().any(|x| x == repo)
+    }
+}
+
+impl Display for TrustedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let repos = if self.repos.is_empty() {
+            String::from("all repos")
+        } else {
+            self.repos.join(", ")
+        };
+        let expires = match self.expires {
+            Some(expires) if self.is_expired() => format!("expired on {}", expires),
+            Some(expires) => format!("expires on {}", expires),
+            None => String::from("never expires"),
+        };
+
+        write!(f, "{}\t{}\t{}", self.id, repos, expires)
+    }
+}
+
+/// Public keys trusted to sign repos, stored as `[key id]` sections with a
+/// `public_key` entry holding the base64 minisign public key, and optionally
+/// the `repos` the key may sign and the date it `expires` on.
+pub struct TrustStore {
+    location: String,
+    keys: Vec<TrustedKey>,
+}
+
+impl TrustStore {
+    /// Loads the trust store, a missing file is an empty trust store.
+    pub fn load(location: &str) -> TrustStore {
+        let keys = match fs::read_to_string(location) {
+            Ok(content) => Document::parse(&content)
+                .get_sections()
+                .iter()
+                .map(TrustedKey::from_section)
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        TrustStore {
+            location: location.to_string(),
+            keys,
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let mut document = Document::new();
+        for key in &self.keys {
+            document.push_section(key.to_section());
+        }
+
+        atomic::replace_files(&[(&self.location, &document.to_string())])
+    }
+
+    pub fn get_keys(&self) -> &Vec<TrustedKey> {
+        &self.keys
+    }
+
+    pub fn get(&self, id: &str) -> Option<&TrustedKey> {
+        self.keys.iter().find(|x| x.id.eq_ignore_ascii_case(id))
+    }
+
+    /// Adds the key, replacing a key with the same id.
+    pub fn insert(&mut self, key: TrustedKey) {
+        self.remove(&key.get_id());
+        self.keys.push(key);
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        let length = self.keys.len();
+        self.keys.retain(|x| !x.id.eq_ignore_ascii_case(id));
+        length != self.keys.len()
+    }
+
+    /// Verifies a detached signature over the content of a repo, returning
+    /// the key that made it. Only keys allowed for the repo that have not
+    /// expired are considered.
+    pub fn verify(
+        &self,
+        content: &[u8],
+        signature: &str,
+        repo: &str,
+    ) -> Result<TrustedKey, String> {
+        let signature = Signature::decode(signature).map_err(|e| e.to_string())?;
+        let mut expired_key = None;
+
+        for key in self.keys.iter().filter(|x| x.is_allowed_for(repo)) {
+            let public_key = match PublicKey::from_base64(&key.public_key) {
+                Ok(public_key) => public_key,
+                Err(_) => continue,
+            };
+            if public_key.verify(content, &signature, false).is_ok() {
+                if key.is_expired() {
+                    expired_key = Some(key.get_id());
+                } else {
+                    return Ok(key.clone());
+                }
+            }
+        }
+
+        match expired_key {
+            Some(id) => Err(format!("Signing key {} has expired", id)),
+            None => Err(format!(
+                "Signature is not valid for any key trusted for repo {}",
+                repo
+            )),
+        }
+    }
+}
@@ -0,0 +1,1271 @@
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::Path,
+    process::Command,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use git2::{
+    build::CheckoutBuilder, AutotagOption, Commit, Cred, CredentialType, DiffOptions,
+    DiffStatsFormat, Direction, FetchOptions, IndexEntry, IndexEntryExtendedFlag, IndexEntryFlag,
+    RemoteCallbacks, Repository, Signature, Status, StatusOptions, SubmoduleUpdateOptions,
+};
+
+use crate::credentials::Credentials;
+
+/// What a git ref names on the remote of a plugin.
+pub enum RemoteRef {
+    Branch(String),
+    Tag(String),
+    Commit(String),
+}
+
+impl RemoteRef {
+    fn refspec(&self) -> String {
+        match self {
+            RemoteRef::Branch(branch) => {
+                format!("+refs/heads/{}:refs/remotes/origin/{}", branch, branch)
+            }
+            RemoteRef::Tag(tag) => format!("+refs/tags/{}:refs/tags/{}", tag, tag),
+            RemoteRef::Commit(commit) => commit.clone(),
+        }
+    }
+
+    fn full_refspec(&self) -> String {
+        match self {
+            RemoteRef::Commit(_) => String::from("+refs/heads/*:refs/remotes/origin/*"),
+            _ => self.refspec(),
+        }
+    }
+}
+
+/// Identity of the stash commits made by the manager.
+const STASH_NAME: &str = "rpi-mesh-plugin-manager";
+const STASH_EMAIL: &str = "root@localhost";
+const STASH_MESSAGE: &str = "Local changes before upgrade";
+
+/// What to do with local changes in a git plugin when it is upgraded.
+#[derive(Debug, PartialEq, Clone)]
+pub enum DirtyPolicy {
+    Abort,
+    Stash,
+    Reset,
+}
+
+impl DirtyPolicy {
+    pub fn parse(policy: &str) -> Option<DirtyPolicy> {
+        match policy {
+            "abort" => Some(DirtyPolicy::Abort),
+            "stash" => Some(DirtyPolicy::Stash),
+            "reset" => Some(DirtyPolicy::Reset),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ChangeKind {
+    New,
+    Untracked,
+    Modified,
+    Deleted,
+}
+
+/// A locally changed file, the path is relative to the repository.
+#[derive(Debug, Clone)]
+pub struct Change {
+    kind: ChangeKind,
+    path: String,
+}
+
+impl Change {
+    pub fn get_path(&self) -> String {
+        self.path.clone()
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.kind == ChangeKind::Deleted
+    }
+
+    /// Whether the file is not in the checked out commit, staged or not.
+    pub fn is_new(&self) -> bool {
+        self.kind == ChangeKind::New || self.kind == ChangeKind::Untracked
+    }
+
+    pub fn is_untracked(&self) -> bool {
+        self.kind == ChangeKind::Untracked
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            ChangeKind::New => "new",
+            ChangeKind::Untracked => "untracked",
+            ChangeKind::Modified => "modified",
+            ChangeKind::Deleted => "deleted",
+        };
+
+        write!(f, "{}: {}", kind, self.path)
+    }
+}
+
+/// Git operations used to install and upgrade git plugins. Every repository
+/// has the plugin location as its `origin` remote.
+pub trait Vcs {
+    /// Creates an empty repository, only checking out `subdir` when given.
+    fn init(&self, path: &str, url: &str, subdir: Option<&str>) -> Result<(), String>;
+
+    /// Asks the remote whether the ref is a branch or a tag, anything else is
+    /// taken as a commit. Without a ref it is the default branch.
+    fn resolve_remote_ref(&self, path: &str, git_ref: Option<&str>) -> Result<RemoteRef, String>;
+
+    /// Fetches only the commit the ref points to. When the remote cannot send
+    /// a single commit, the whole history of the ref is fetched instead, and
+    /// of all branches for a commit.
+    fn fetch(&self, path: &str, remote_ref: &RemoteRef) -> Result<(), String>;
+
+    /// Checks out the fetched ref, branches as a local branch, tags and
+    /// commits detached. Locally modified files are only overwritten when
+    /// forced.
+    fn checkout(
+        &self,
+        path: &str,
+        remote_ref: &RemoteRef,
+        subdir: Option<&str>,
+        force: bool,
+    ) -> Result<(), String>;
+
+    /// Initializes and checks out the submodules recursively, only the ones
+    /// in `subdir` when given.
+    fn update_submodules(
+        &self,
+        path: &str,
+        subdir: Option<&str>,
+        force: bool,
+    ) -> Result<(), String>;
+
+    /// Paths and checked out commits of the submodules, recursively.
+    fn submodules(&self, path: &str) -> Result<Vec<(String, String)>, String>;
+
+    /// Hash of the checked out commit.
+    fn head(&self, path: &str) -> Result<String, String>;
+
+    /// Files with local modifications, untracked files only when asked for.
+    fn status(&self, path: &str, include_untracked: bool) -> Result<Vec<Change>, String>;
+
+    /// Summary of the changes to tracked files since the checked out commit,
+    /// like `git diff --stat`.
+    fn diff_summary(&self, path: &str) -> Result<String, String>;
+
+    /// Stashes the local modifications, untracked files only when asked for.
+    fn stash(&self, path: &str, include_untracked: bool) -> Result<(), String>;
+
+    /// Reapplies the last stash and drops it when it applied cleanly.
+    fn stash_pop(&self, path: &str) -> Result<(), String>;
+}
+
+/// Returns the backend with the given config name: `builtin`, which falls
+/// back to the `git` command, `builtin-only` or `command`.
+pub fn backend(name: &str, credentials: Credentials) -> Option<Box<dyn Vcs>> {
+    match name {
+        "builtin" => Some(Box::new(FallbackGit::new(credentials))),
+        "builtin-only" => Some(Box::new(BuiltinGit::new(credentials))),
+        "command" => Some(Box::new(GitCommand::new(credentials))),
+        _ => None,
+    }
+}
+
+/// Submodules are configured in the `.gitmodules` file at the root, so it
+/// is part of every sparse checkout.
+const SUBMODULE_CONFIG: &str = ".gitmodules";
+
+fn sparse_patterns(subdir: &str) -> Vec<String> {
+    vec![format!("/{}/", subdir), format!("/{}", SUBMODULE_CONFIG)]
+}
+
+/// Times libgit2 may ask for credentials before giving up, it keeps asking
+/// while the server rejects them.
+const CREDENTIAL_ATTEMPTS: usize = 3;
+
+/// In-process git backend, works without git installed.
+pub struct BuiltinGit {
+    credentials: Credentials,
+}
+
+impl BuiltinGit {
+    pub fn new(credentials: Credentials) -> BuiltinGit {
+        BuiltinGit { credentials }
+    }
+
+    fn open(path: &str) -> Result<Repository, String> {
+        Repository::open(path).map_err(git_error)
+    }
+
+    /// Folders of a sparse checkout, the files outside of them are missing
+    /// but not deleted.
+    fn sparse_pathspecs(repository: &Repository) -> Vec<String> {
+        let is_sparse = repository
+            .config()
+            .and_then(|x| x.get_bool("core.sparseCheckout"))
+            .unwrap_or(false);
+        if !is_sparse {
+            return Vec::new();
+        }
+
+        fs::read_to_string(repository.path().join("info/sparse-checkout"))
+            .unwrap_or_default()
+            .lines()
+            .filter(|x| !x.trim().is_empty())
+            .map(|x| x.trim().trim_matches('/').to_string())
+            .collect()
+    }
+
+    /// Callbacks answering credential requests with the credentials of the
+    /// host of the requested url.
+    fn callbacks(&self) -> RemoteCallbacks<'_> {
+        let mut attempts = 0;
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(move |url, username_from_url, allowed| {
+            attempts += 1;
+            let credential = match self.credentials.get(url) {
+                Some(credential) if attempts <= CREDENTIAL_ATTEMPTS => credential,
+                Some(_) => return Err(git2::Error::from_str("Authentication failed")),
+                None => return Err(git2::Error::from_str("No credentials for the host")),
+            };
+            let username = username_from_url
+                .map(|x| x.to_string())
+                .or(credential.get_username())
+                .unwrap_or(String::from("git"));
+
+            if allowed.contains(CredentialType::USERNAME) {
+                return Cred::username(&username);
+            }
+            if allowed.contains(CredentialType::SSH_KEY) {
+                if let Some(ssh_key) = credential.get_ssh_key() {
+                    return Cred::ssh_key(&username, None, Path::new(&ssh_key), None);
+                }
+            }
+            if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                if let Some((username, password)) = credential.get_git_login() {
+                    return Cred::userpass_plaintext(&username, &password);
+                }
+            }
+            Err(git2::Error::from_str("No usable credentials for the host"))
+        });
+        callbacks
+    }
+
+    fn fetch_options(&self) -> FetchOptions<'_> {
+        let mut options = FetchOptions::new();
+        options
+            .remote_callbacks(self.callbacks())
+            .download_tags(AutotagOption::None);
+        options
+    }
+
+    fn update_submodules(
+        &self,
+        repository: &Repository,
+        subdir: Option<&str>,
+        force: bool,
+    ) -> Result<(), git2::Error> {
+        for mut submodule in repository.submodules()? {
+            if subdir.is_some_and(|x| !submodule.path().starts_with(x)) {
+                continue;
+            }
+
+            let mut checkout = CheckoutBuilder::new();
+            if force {
+                checkout.force();
+            }
+            let mut options = SubmoduleUpdateOptions::new();
+            options.checkout(checkout).fetch(self.fetch_options());
+
+            submodule.sync()?;
+            submodule.update(true, Some(&mut options))?;
+            self.update_submodules(&submodule.open()?, None, force)?;
+        }
+        Ok(())
+    }
+
+    fn list_submodules(
+        repository: &Repository,
+        prefix: &Path,
+        submodules: &mut Vec<(String, String)>,
+    ) -> Result<(), git2::Error> {
+        for submodule in repository.submodules()? {
+            // Submodules that are not checked out cannot be opened.
+            let Ok(submodule_repository) = submodule.open() else {
+                continue;
+            };
+            let path = prefix.join(submodule.path());
+            if let Some(commit) = submodule.workdir_id() {
+                submodules.push((path.display().to_string(), commit.to_string()));
+            }
+            BuiltinGit::list_submodules(&submodule_repository, &path, submodules)?;
+        }
+        Ok(())
+    }
+
+    /// Marks the files outside of a sparse checkout as skipped in the index,
+    /// the way git does, so they do not show up as deleted.
+    fn skip_outside(
+        repository: &Repository,
+        commit: &Commit,
+        subdir: &str,
+    ) -> Result<(), git2::Error> {
+        let mut index = repository.index()?;
+        index.read_tree(&commit.tree()?)?;
+
+        let prefix = format!("{}/", subdir);
+        let outside: Vec<IndexEntry> = index
+            .iter()
+            .filter(|x| {
+                !x.path.starts_with(prefix.as_bytes()) && x.path != SUBMODULE_CONFIG.as_bytes()
+            })
+            .collect();
+        for mut entry in outside {
+            entry.flags |= IndexEntryFlag::EXTENDED.bits();
+            entry.flags_extended |= IndexEntryExtendedFlag::SKIP_WORKTREE.bits();
+            index.add(&entry)?;
+        }
+        index.write()
+    }
+}
+
+fn git_error(e: git2::Error) -> String {
+    format!("Git error: {}", e.message())
+}
+
+impl Vcs for BuiltinGit {
+    fn init(&self, path: &str, url: &str, subdir: Option<&str>) -> Result<(), String> {
+        let repository = Repository::init(path).map_err(git_error)?;
+        repository.remote("origin", url).map_err(git_error)?;
+
+        // Written the way `git sparse-checkout` does, so the git command
+        // keeps the checkout sparse too.
+        if let Some(subdir) = subdir {
+            repository
+                .config()
+                .and_then(|mut x| x.set_bool("core.sparseCheckout", true))
+                .map_err(git_error)?;
+            let info_path = repository.path().join("info");
+            fs::create_dir_all(&info_path).map_err(|e| e.to_string())?;
+            fs::write(
+                info_path.join("sparse-checkout"),
+                format!("{}\n", sparse_patterns(subdir).join("\n")),
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn resolve_remote_ref(&self, path: &str, git_ref: Option<&str>) -> Result<RemoteRef, String> {
+        let repository = BuiltinGit::open(path)?;
+        let mut remote = repository.find_remote("origin").map_err(git_error)?;
+        remote
+            .connect_auth(Direction::Fetch, Some(self.callbacks()), None)
+            .map_err(git_error)?;
+
+        let git_ref = match git_ref {
+            Some(git_ref) => git_ref,
+            None => {
+                let default_branch = remote.default_branch().map_err(git_error)?;
+                return default_branch
+                    .as_str()
+                    .and_then(|x| x.strip_prefix("refs/heads/"))
+                    .map(|x| RemoteRef::Branch(x.to_string()))
+                    .ok_or_else(|| {
+                        String::from("Cannot find the default branch of the repository!")
+                    });
+            }
+        };
+
+        let branch = format!("refs/heads/{}", git_ref);
+        let tag = format!("refs/tags/{}", git_ref);
+        let remote_refs: Vec<String> = remote
+            .list()
+            .map_err(git_error)?
+            .iter()
+            .map(|x| x.name().to_string())
+            .collect();
+
+        if remote_refs.contains(&branch) {
+            Ok(RemoteRef::Branch(git_ref.to_string()))
+        } else if remote_refs.contains(&tag) {
+            Ok(RemoteRef::Tag(git_ref.to_string()))
+        } else {
+            Ok(RemoteRef::Commit(git_ref.to_string()))
+        }
+    }
+
+    fn fetch(&self, path: &str, remote_ref: &RemoteRef) -> Result<(), String> {
+        let repository = BuiltinGit::open(path)?;
+        let mut remote = repository.find_remote("origin").map_err(git_error)?;
+
+        let mut options = self.fetch_options();
+        options.depth(1);
+        if remote
+            .fetch(&[remote_ref.refspec()], Some(&mut options), None)
+            .is_ok()
+        {
+            return Ok(());
+        }
+
+        // A depth of i32::MAX unshallows the repository.
+        let mut options = self.fetch_options();
+        if repository.is_shallow() {
+            options.depth(i32::MAX);
+        }
+        remote
+            .fetch(&[remote_ref.full_refspec()], Some(&mut options), None)
+            .map_err(git_error)
+    }
+
+    fn checkout(
+        &self,
+        path: &str,
+        remote_ref: &RemoteRef,
+        subdir: Option<&str>,
+        force: bool,
+    ) -> Result<(), String> {
+        let repository = BuiltinGit::open(path)?;
+        let target = match remote_ref {
+            RemoteRef::Branch(branch) => format!("refs/remotes/origin/{}", branch),
+            RemoteRef::Tag(tag) => format!("refs/tags/{}", tag),
+            RemoteRef::Commit(commit) => commit.clone(),
+        };
+        let commit = repository
+            .revparse_single(&target)
+            .and_then(|x| x.peel_to_commit())
+            .map_err(git_error)?;
+
+        let mut options = CheckoutBuilder::new();
+        if let Some(subdir) = subdir {
+            options.path(subdir).path(SUBMODULE_CONFIG);
+        }
+        if force {
+            options.force();
+        }
+        repository
+            .checkout_tree(commit.as_object(), Some(&mut options))
+            .map_err(git_error)?;
+        if let Some(subdir) = subdir {
+            BuiltinGit::skip_outside(&repository, &commit, subdir).map_err(git_error)?;
+        }
+
+        match remote_ref {
+            RemoteRef::Branch(branch) => {
+                let reference = format!("refs/heads/{}", branch);
+                repository
+                    .reference(&reference, commit.id(), true, "checkout")
+                    .and_then(|_| repository.set_head(&reference))
+                    .map_err(git_error)
+            }
+            _ => repository.set_head_detached(commit.id()).map_err(git_error),
+        }
+    }
+
+    fn update_submodules(
+        &self,
+        path: &str,
+        subdir: Option<&str>,
+        force: bool,
+    ) -> Result<(), String> {
+        self.update_submodules(&BuiltinGit::open(path)?, subdir, force)
+            .map_err(git_error)
+    }
+
+    fn submodules(&self, path: &str) -> Result<Vec<(String, String)>, String> {
+        let mut submodules = Vec::new();
+        BuiltinGit::list_submodules(&BuiltinGit::open(path)?, Path::new(""), &mut submodules)
+            .map_err(git_error)?;
+        Ok(submodules)
+    }
+
+    fn head(&self, path: &str) -> Result<String, String> {
+        BuiltinGit::open(path)?
+            .head()
+            .and_then(|x| x.peel_to_commit())
+            .map(|x| x.id().to_string())
+            .map_err(git_error)
+    }
+
+    fn status(&self, path: &str, include_untracked: bool) -> Result<Vec<Change>, String> {
+        let repository = BuiltinGit::open(path)?;
+        let mut options = StatusOptions::new();
+        options
+            .include_untracked(include_untracked)
+            .recurse_untracked_dirs(include_untracked)
+            .exclude_submodules(true);
+        for pathspec in BuiltinGit::sparse_pathspecs(&repository) {
+            options.pathspec(pathspec);
+        }
+
+        let statuses = repository.statuses(Some(&mut options)).map_err(git_error)?;
+        Ok(statuses
+            .iter()
+            .filter_map(|x| {
+                let status = x.status();
+                let kind = if status.intersects(Status::INDEX_NEW) {
+                    ChangeKind::New
+                } else if status.intersects(Status::WT_NEW) {
+                    ChangeKind::Untracked
+                } else if status.intersects(Status::WT_DELETED | Status::INDEX_DELETED) {
+                    ChangeKind::Deleted
+                } else {
+                    ChangeKind::Modified
+                };
+                x.path().map(|path| Change {
+                    kind,
+                    path: path.to_string(),
+                })
+            })
+            .collect())
+    }
+
+    fn diff_summary(&self, path: &str) -> Result<String, String> {
+        let repository = BuiltinGit::open(path)?;
+        let tree = repository
+            .head()
+            .and_then(|x| x.peel_to_tree())
+            .map_err(git_error)?;
+        let mut options = DiffOptions::new();
+        options.ignore_submodules(true);
+        for pathspec in BuiltinGit::sparse_pathspecs(&repository) {
+            options.pathspec(pathspec);
+        }
+
+        let stats = repository
+            .diff_tree_to_workdir_with_index(Some(&tree), Some(&mut options))
+            .and_then(|x| x.stats())
+            .and_then(|x| x.to_buf(DiffStatsFormat::FULL, 80))
+            .map_err(git_error)?;
+        Ok(stats.as_str().unwrap_or_default().trim_end().to_string())
+    }
+
+    fn stash(&self, path: &str, include_untracked: bool) -> Result<(), String> {
+        let mut repository = BuiltinGit::open(path)?;
+        let signature = Signature::now(STASH_NAME, STASH_EMAIL).map_err(git_error)?;
+        let flags = if include_untracked {
+            git2::StashFlags::INCLUDE_UNTRACKED
+        } else {
+            git2::StashFlags::DEFAULT
+        };
+        repository
+            .stash_save(&signature, STASH_MESSAGE, Some(flags))
+            .map(|_| ())
+            .map_err(git_error)
+    }
+
+    fn stash_pop(&self, path: &str) -> Result<(), String> {
+        BuiltinGit::open(path)?
+            .stash_pop(0, None)
+            .map_err(git_error)
+    }
+}
+
+/// Url of the origin remote of the repository at `path`, there is none
+/// before `init`.
+fn origin_url(path: &str) -> Option<String> {
+    let output = Command::new("git")
+        .args(["-C", path, "config", "--get", "remote.origin.url"])
+        .output()
+        .ok()?;
+    let url = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !url.is_empty()).then_some(url)
+}
+
+/// Backend running the `git` command, for setups where the builtin backend
+/// does not work.
+pub struct GitCommand {
+    credentials: Credentials,
+}
+
+impl GitCommand {
+    pub fn new(credentials: Credentials) -> GitCommand {
+        GitCommand { credentials }
+    }
+
+    /// Runs git without prompting. Http credentials are passed as scoped
+    /// `extraHeader` settings through the environment, so they do not show
+    /// up in the process list. Only the ssh key of the host of the origin
+    /// remote is offered to ssh.
+    fn run(&self, path: &str, args: &[&str]) -> Result<String, String> {
+        let mut command = Command::new("git");
+        command
+            .arg("-C")
+            .arg(path)
+            .args(args)
+            .env("GIT_TERMINAL_PROMPT", "0");
+
+        let mut count = 0;
+        for (host, credential) in self.credentials.get_hosts() {
+            if let Some((username, password)) = credential.get_git_login() {
+                let header = STANDARD.encode(format!("{}:{}", username, password));
+                for scheme in ["https", "http"] {
+                    command
+                        .env(
+                            format!("GIT_CONFIG_KEY_{}", count),
+                            format!("http.{}://{}/.extraHeader", scheme, host),
+                        )
+                        .env(
+                            format!("GIT_CONFIG_VALUE_{}", count),
+                            format!("Authorization: Basic {}", header),
+                        );
+                    count += 1;
+                }
+            }
+        }
+        command.env("GIT_CONFIG_COUNT", count.to_string());
+        if let Some(ssh_command) = origin_url(path).and_then(|x| self.ssh_command(&x)) {
+            command.env("GIT_SSH_COMMAND", ssh_command);
+        }
+
+        let output = command.output().map_err(|e| {
+            format!(
+                "Cannot execute git command! Check if it is installed correctly! {}",
+                e
+            )
+        })?;
+
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout)
+                .trim_end()
+                .to_string())
+        } else {
+            Err(format!(
+                "Git error: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+
+    /// Ssh command offering only the ssh key of the host of the url.
+    fn ssh_command(&self, url: &str) -> Option<String> {
+        let ssh_key = self.credentials.get(url)?.get_ssh_key()?;
+        Some(format!(
+            "ssh -o IdentitiesOnly=yes -i '{}'",
+            ssh_key.replace('\'', "'\\''")
+        ))
+    }
+}
+
+impl Vcs for GitCommand {
+    fn init(&self, path: &str, url: &str, subdir: Option<&str>) -> Result<(), String> {
+        self.run(path, &["init", "--quiet"])?;
+        self.run(path, &["remote", "add", "origin", url])?;
+        if let Some(subdir) = subdir {
+            let patterns = sparse_patterns(subdir);
+            let mut args = vec!["sparse-checkout", "set", "--no-cone"];
+            args.extend(patterns.iter().map(|x| x.as_str()));
+            self.run(path, &args)?;
+        }
+        Ok(())
+    }
+
+    fn resolve_remote_ref(&self, path: &str, git_ref: Option<&str>) -> Result<RemoteRef, String> {
+        let git_ref = match git_ref {
+            Some(git_ref) => git_ref,
+            None => {
+                return self
+                    .run(path, &["ls-remote", "--symref", "origin", "HEAD"])?
+                    .lines()
+                    .find_map(|x| x.strip_prefix("ref: refs/heads/"))
+                    .and_then(|x| x.split('\t').next())
+                    .map(|x| RemoteRef::Branch(x.to_string()))
+                    .ok_or_else(|| {
+                        String::from("Cannot find the default branch of the repository!")
+                    });
+            }
+        };
+
+        let branch = format!("refs/heads/{}", git_ref);
+        let tag = format!("refs/tags/{}", git_ref);
+        let output = self.run(path, &["ls-remote", "origin", &branch, &tag])?;
+        let remote_refs: Vec<&str> = output
+            .lines()
+            .filter_map(|x| x.split('\t').nth(1))
+            .collect();
+
+        if remote_refs.contains(&branch.as_str()) {
+            Ok(RemoteRef::Branch(git_ref.to_string()))
+        } else if remote_refs.contains(&tag.as_str()) {
+            Ok(RemoteRef::Tag(git_ref.to_string()))
+        } else {
+            Ok(RemoteRef::Commit(git_ref.to_string()))
+        }
+    }
+
+    fn fetch(&self, path: &str, remote_ref: &RemoteRef) -> Result<(), String> {
+        if self
+            .run(
+                path,
+                &[
+                    "fetch",
+                    "--quiet",
+                    "--no-tags",
+                    "--depth",
+                    "1",
+                    "origin",
+                    &remote_ref.refspec(),
+                ],
+            )
+            .is_ok()
+        {
+            return Ok(());
+        }
+
+        let full_refspec = remote_ref.full_refspec();
+        let mut args = vec!["fetch", "--quiet", "--no-tags"];
+        if self.run(path, &["rev-parse", "--is-shallow-repository"])? == "true" {
+            args.push("--unshallow");
+        }
+        args.extend(["origin", &full_refspec]);
+        self.run(path, &args).map(|_| ())
+    }
+
+    fn checkout(
+        &self,
+        path: &str,
+        remote_ref: &RemoteRef,
+        _subdir: Option<&str>,
+        force: bool,
+    ) -> Result<(), String> {
+        let target = match remote_ref {
+            RemoteRef::Branch(branch) => format!("refs/remotes/origin/{}", branch),
+            RemoteRef::Tag(tag) => format!("refs/tags/{}^{{commit}}", tag),
+            RemoteRef::Commit(commit) => format!("{}^{{commit}}", commit),
+        };
+        let mut args = vec!["checkout", "--quiet"];
+        if force {
+            args.push("--force");
+        }
+        match remote_ref {
+            RemoteRef::Branch(branch) => args.extend(["-B", branch, &target]),
+            _ => args.extend(["--detach", &target]),
+        }
+        self.run(path, &args).map(|_| ())
+    }
+
+    fn update_submodules(
+        &self,
+        path: &str,
+        subdir: Option<&str>,
+        force: bool,
+    ) -> Result<(), String> {
+        let mut sync = vec!["submodule", "sync", "--quiet", "--recursive"];
+        let mut update = vec!["submodule", "update", "--quiet", "--init", "--recursive"];
+        if force {
+            update.push("--force");
+        }
+        if let Some(subdir) = subdir {
+            sync.extend(["--", subdir]);
+            update.extend(["--", subdir]);
+        }
+        self.run(path, &sync)?;
+        self.run(path, &update).map(|_| ())
+    }
+
+    fn submodules(&self, path: &str) -> Result<Vec<(String, String)>, String> {
+        // Lines look like ` <commit> <path> (<describe>)`, with a `-` instead
+        // of the space for submodules that are not checked out.
+        Ok(self
+            .run(path, &["submodule", "status", "--recursive"])?
+            .lines()
+            .filter(|x| !x.starts_with('-'))
+            .filter_map(|x| {
+                let mut parts = x.get(1..)?.split(' ');
+                let commit = parts.next()?;
+                let path = parts.next()?;
+                Some((path.to_string(), commit.to_string()))
+            })
+            .collect())
+    }
+
+    fn head(&self, path: &str) -> Result<String, String> {
+        self.run(path, &["rev-parse", "HEAD"])
+    }
+
+    fn status(&self, path: &str, include_untracked: bool) -> Result<Vec<Change>, String> {
+        let untracked_files = if include_untracked {
+            "--untracked-files=all"
+        } else {
+            "--untracked-files=no"
+        };
+        // Paths are NUL terminated and not quoted with -z.
+        Ok(self
+            .run(
+                path,
+                &[
+                    "status",
+                    "--porcelain",
+                    "-z",
+                    "--no-renames",
+                    untracked_files,
+                ],
+            )?
+            .split('\0')
+            .filter_map(|x| {
+                let (status, path) = (x.get(..2)?, x.get(3..)?);
+                let kind = if status == "??" {
+                    ChangeKind::Untracked
+                } else if status.contains('A') {
+                    ChangeKind::New
+                } else if status.contains('D') {
+                    ChangeKind::Deleted
+                } else {
+                    ChangeKind::Modified
+                };
+                Some(Change {
+                    kind,
+                    path: path.to_string(),
+                })
+            })
+            .collect())
+    }
+
+    fn diff_summary(&self, path: &str) -> Result<String, String> {
+        self.run(path, &["diff", "HEAD", "--stat=80", "--ignore-submodules"])
+    }
+
+    fn stash(&self, path: &str, include_untracked: bool) -> Result<(), String> {
+        let user_name = format!("user.name={}", STASH_NAME);
+        let user_email = format!("user.email={}", STASH_EMAIL);
+        let mut args = vec![
+            "-c",
+            &user_name,
+            "-c",
+            &user_email,
+            "stash",
+            "push",
+            "--quiet",
+            "--message",
+            STASH_MESSAGE,
+        ];
+        if include_untracked {
+            args.push("--include-untracked");
+        }
+        self.run(path, &args).map(|_| ())
+    }
+
+    fn stash_pop(&self, path: &str) -> Result<(), String> {
+        self.run(path, &["stash", "pop", "--quiet"]).map(|_| ())
+    }
+}
+
+/// The builtin backend, retrying with the `git` command when it fails to
+/// talk to a remote or read a repository, for example for protocols or
+/// repository formats libgit2 does not support. Operations changing the work
+/// tree are not retried, a failed one may have been half applied.
+pub struct FallbackGit {
+    builtin: BuiltinGit,
+    command: GitCommand,
+}
+
+impl FallbackGit {
+    pub fn new(credentials: Credentials) -> FallbackGit {
+        FallbackGit {
+            builtin: BuiltinGit::new(credentials.clone()),
+            command: GitCommand::new(credentials),
+        }
+    }
+
+    fn with_fallback<T>(
+        &self,
+        builtin: impl FnOnce(&BuiltinGit) -> Result<T, String>,
+        command: impl FnOnce(&GitCommand) -> Result<T, String>,
+    ) -> Result<T, String> {
+        builtin(&self.builtin).or_else(|builtin_error| {
+            command(&self.command).map_err(|command_error| {
+                format!(
+                    "{} Falling back to the git command failed too! {}",
+                    builtin_error, command_error
+                )
+            })
+        })
+    }
+}
+
+impl Vcs for FallbackGit {
+    fn init(&self, path: &str, url: &str, subdir: Option<&str>) -> Result<(), String> {
+        self.builtin.init(path, url, subdir)
+    }
+
+    fn resolve_remote_ref(&self, path: &str, git_ref: Option<&str>) -> Result<RemoteRef, String> {
+        self.with_fallback(
+            |x| Vcs::resolve_remote_ref(x, path, git_ref),
+            |x| Vcs::resolve_remote_ref(x, path, git_ref),
+        )
+    }
+
+    fn fetch(&self, path: &str, remote_ref: &RemoteRef) -> Result<(), String> {
+        self.with_fallback(
+            |x| Vcs::fetch(x, path, remote_ref),
+            |x| Vcs::fetch(x, path, remote_ref),
+        )
+    }
+
+    fn checkout(
+        &self,
+        path: &str,
+        remote_ref: &RemoteRef,
+        subdir: Option<&str>,
+        force: bool,
+    ) -> Result<(), String> {
+        self.builtin.checkout(path, remote_ref, subdir, force)
+    }
+
+    fn update_submodules(
+        &self,
+        path: &str,
+        subdir: Option<&str>,
+        force: bool,
+    ) -> Result<(), String> {
+        self.with_fallback(
+            |x| Vcs::update_submodules(x, path, subdir, force),
+            |x| Vcs::update_submodules(x, path, subdir, force),
+        )
+    }
+
+    fn submodules(&self, path: &str) -> Result<Vec<(String, String)>, String> {
+        self.with_fallback(|x| Vcs::submodules(x, path), |x| Vcs::submodules(x, path))
+    }
+
+    fn head(&self, path: &str) -> Result<String, String> {
+        self.with_fallback(|x| Vcs::head(x, path), |x| Vcs::head(x, path))
+    }
+
+    fn status(&self, path: &str, include_untracked: bool) -> Result<Vec<Change>, String> {
+        self.with_fallback(
+            |x| Vcs::status(x, path, include_untracked),
+            |x| Vcs::status(x, path, include_untracked),
+        )
+    }
+
+    fn diff_summary(&self, path: &str) -> Result<String, String> {
+        self.with_fallback(
+            |x| Vcs::diff_summary(x, path),
+            |x| Vcs::diff_summary(x, path),
+        )
+    }
+
+    fn stash(&self, path: &str, include_untracked: bool) -> Result<(), String> {
+        self.builtin.stash(path, include_untracked)
+    }
+
+    fn stash_pop(&self, path: &str) -> Result<(), String> {
+        self.builtin.stash_pop(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Reply, TestServer};
+    use tempfile::TempDir;
+
+    #[test]
+    fn fallback_reports_both_errors() {
+        let root = TempDir::new().unwrap();
+        let path = root.path().display().to_string();
+
+        let error = FallbackGit::new(Credentials::default())
+            .head(&path)
+            .unwrap_err();
+
+        assert!(error.contains("Falling back to the git command failed too!"));
+    }
+
+    #[test]
+    fn fallback_uses_the_builtin_backend_first() {
+        let root = TempDir::new().unwrap();
+        let path = root.path().display().to_string();
+        let vcs = FallbackGit::new(Credentials::default());
+
+        vcs.init(&path, "https://example.org/web.git", None)
+            .unwrap();
+
+        assert!(vcs.status(&path, true).unwrap().is_empty());
+    }
+
+    /// Repository with one commit holding `tracked` and a file with a name
+    /// git quotes, then a modified `tracked` and an untracked file.
+    fn dirty_repository() -> TempDir {
+        let root = TempDir::new().unwrap();
+        let repository = Repository::init(root.path()).unwrap();
+        fs::write(root.path().join("tracked"), "one\n").unwrap();
+        fs::write(root.path().join("tab\tnämé"), "one\n").unwrap();
+        let mut index = repository.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now(STASH_NAME, STASH_EMAIL).unwrap();
+        repository
+            .commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+
+        fs::write(root.path().join("tracked"), "one\ntwo\n").unwrap();
+        fs::write(root.path().join("tab\tnämé"), "").unwrap();
+        fs::write(root.path().join("build.out"), "output").unwrap();
+        root
+    }
+
+    fn paths(changes: &[Change]) -> Vec<String> {
+        let mut paths: Vec<String> = changes.iter().map(|x| x.get_path()).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn untracked_files_are_only_listed_when_asked_for() {
+        let root = dirty_repository();
+        let path = root.path().display().to_string();
+        let backends: [Box<dyn Vcs>; 2] = [
+            Box::new(BuiltinGit::new(Credentials::default())),
+            Box::new(GitCommand::new(Credentials::default())),
+        ];
+
+        for vcs in backends {
+            let changes = vcs.status(&path, false).unwrap();
+            assert_eq!(paths(&changes), ["tab\tnämé", "tracked"]);
+            assert!(changes.iter().all(|x| !x.is_new()));
+
+            let changes = vcs.status(&path, true).unwrap();
+            assert_eq!(paths(&changes), ["build.out", "tab\tnämé", "tracked"]);
+            assert!(changes
+                .iter()
+                .any(|x| x.get_path() == "build.out" && x.is_untracked()));
+        }
+    }
+
+    #[test]
+    fn diff_summary_counts_changed_lines() {
+        let root = dirty_repository();
+        let path = root.path().display().to_string();
+
+        for summary in [
+            BuiltinGit::new(Credentials::default()).diff_summary(&path),
+            GitCommand::new(Credentials::default()).diff_summary(&path),
+        ] {
+            let summary = summary.unwrap();
+            assert!(
+                summary
+                    .lines()
+                    .any(|x| x.trim().starts_with("tracked") && x.ends_with("| 1 +")),
+                "{}",
+                summary
+            );
+            assert!(summary.contains("2 files changed, 1 insertion(+), 1 deletion(-)"));
+            assert!(!summary.contains("build.out"));
+        }
+    }
+
+    #[test]
+    fn ssh_keys_are_only_offered_to_their_host() {
+        let root = TempDir::new().unwrap();
+        let path = root.path().display().to_string();
+        let vcs = GitCommand::new(Credentials::parse(
+            "[example.org]\nssh_key=/root/.ssh/example\n\n\
+             [github.com]\nssh_key=/root/.ssh/git'hub\n",
+        ));
+
+        assert_eq!(
+            vcs.ssh_command("git@github.com:mesh/web.git").as_deref(),
+            Some("ssh -o IdentitiesOnly=yes -i '/root/.ssh/git'\\''hub'")
+        );
+        assert!(vcs.ssh_command("git@gitlab.com:mesh/web.git").is_none());
+
+        assert_eq!(origin_url(&path), None);
+        vcs.init(&path, "ssh://git@example.org/mesh/web.git", None)
+            .unwrap();
+        assert_eq!(
+            origin_url(&path)
+                .and_then(|x| vcs.ssh_command(&x))
+                .as_deref(),
+            Some("ssh -o IdentitiesOnly=yes -i '/root/.ssh/example'")
+        );
+    }
+
+    #[test]
+    fn git_errors_do_not_echo_credentials() {
+        let root = TempDir::new().unwrap();
+        let path = root.path().display().to_string();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/web.git", listener.local_addr().unwrap());
+        drop(listener);
+        let credentials = Credentials::parse(&format!(
+            "[{}]\nusername=mesh\npassword=secret-password\n",
+            crate::credentials::host_of(&url).unwrap()
+        ));
+        let header = STANDARD.encode("mesh:secret-password");
+        let backends: [Box<dyn Vcs>; 2] = [
+            Box::new(BuiltinGit::new(credentials.clone())),
+            Box::new(GitCommand::new(credentials)),
+        ];
+
+        for vcs in backends {
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir(&path).unwrap();
+            vcs.init(&path, &url, None).unwrap();
+            let error = vcs.resolve_remote_ref(&path, None).err().unwrap();
+            assert!(!error.contains("secret-password"), "{}", error);
+            assert!(!error.contains(&header), "{}", error);
+        }
+    }
+
+    /// Commits the files to the repository at `path`, creating it first.
+    fn commit(path: &Path, files: &[(&str, &str)]) -> String {
+        let repository = Repository::open(path)
+            .or_else(|_| Repository::init(path))
+            .unwrap();
+        for (name, content) in files {
+            fs::write(path.join(name), content).unwrap();
+        }
+        let mut index = repository.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now(STASH_NAME, STASH_EMAIL).unwrap();
+        let parent = repository.head().ok().and_then(|x| x.peel_to_commit().ok());
+        repository
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "commit",
+                &tree,
+                &parent.iter().collect::<Vec<_>>(),
+            )
+            .unwrap()
+            .to_string()
+    }
+
+    /// Repository with two commits on `main`, the second one also on
+    /// `other`. Returns both commits.
+    fn upstream(path: &Path) -> (String, String) {
+        Repository::init(path)
+            .unwrap()
+            .set_head("refs/heads/main")
+            .unwrap();
+        let first = commit(path, &[("file", "one")]);
+        let second = commit(path, &[("file", "two")]);
+        let repository = Repository::open(path).unwrap();
+        repository
+            .branch(
+                "other",
+                &repository.head().unwrap().peel_to_commit().unwrap(),
+                false,
+            )
+            .unwrap();
+        (first, second)
+    }
+
+    #[test]
+    fn single_refs_are_fetched_shallow() {
+        let root = TempDir::new().unwrap();
+        let (first, second) = upstream(&root.path().join("upstream"));
+        let clone = root.path().join("clone");
+        let path = clone.display().to_string();
+        fs::create_dir(&clone).unwrap();
+        let vcs = GitCommand::new(Credentials::default());
+
+        vcs.init(
+            &path,
+            &format!("file://{}/upstream", root.path().display()),
+            None,
+        )
+        .unwrap();
+        let remote_ref = vcs.resolve_remote_ref(&path, None).unwrap();
+        assert!(matches!(&remote_ref, RemoteRef::Branch(x) if x == "main"));
+        vcs.fetch(&path, &remote_ref).unwrap();
+        vcs.checkout(&path, &remote_ref, None, false).unwrap();
+
+        let repository = Repository::open(&clone).unwrap();
+        assert!(repository.is_shallow());
+        assert!(repository
+            .find_reference("refs/remotes/origin/other")
+            .is_err());
+        assert!(repository
+            .find_commit(git2::Oid::from_str(&first).unwrap())
+            .is_err());
+        assert_eq!(vcs.head(&path).unwrap(), second);
+        assert_eq!(fs::read_to_string(clone.join("file")).unwrap(), "two");
+
+        let remote_ref = RemoteRef::Commit(first.clone());
+        vcs.fetch(&path, &remote_ref).unwrap();
+        vcs.checkout(&path, &remote_ref, None, false).unwrap();
+        assert_eq!(vcs.head(&path).unwrap(), first);
+        assert!(repository
+            .find_reference("refs/remotes/origin/other")
+            .is_err());
+    }
+
+    #[test]
+    fn remotes_refusing_single_commits_send_the_history() {
+        let root = TempDir::new().unwrap();
+        let upstream_path = root.path().join("upstream");
+        let (first, second) = upstream(&upstream_path);
+        // Neither the local transport of libgit2 nor dumb http can send a
+        // single commit.
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(&upstream_path)
+            .arg("update-server-info")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let git_folder = upstream_path.join(".git");
+        let server = TestServer::start(move |request| {
+            let (path, _) = request
+                .get_path()
+                .split_once('?')
+                .unwrap_or((request.get_path(), ""));
+            match fs::read(git_folder.join(path.trim_start_matches('/'))) {
+                Ok(content) => Reply::bytes(content),
+                Err(_) => Reply::status(404),
+            }
+        });
+        let backends: [(Box<dyn Vcs>, String); 2] = [
+            (
+                Box::new(BuiltinGit::new(Credentials::default())),
+                upstream_path.display().to_string(),
+            ),
+            (
+                Box::new(GitCommand::new(Credentials::default())),
+                server.url(""),
+            ),
+        ];
+
+        for (index, (vcs, url)) in backends.iter().enumerate() {
+            let clone = root.path().join(format!("clone-{}", index));
+            let path = clone.display().to_string();
+            fs::create_dir(&clone).unwrap();
+            vcs.init(&path, url, None).unwrap();
+
+            let branch = RemoteRef::Branch(String::from("main"));
+            vcs.fetch(&path, &branch).unwrap();
+            vcs.checkout(&path, &branch, None, false).unwrap();
+            assert_eq!(vcs.head(&path).unwrap(), second);
+            let repository = Repository::open(&clone).unwrap();
+            assert!(!repository.is_shallow());
+            assert!(repository
+                .find_reference("refs/remotes/origin/other")
+                .is_err());
+
+            let commit = RemoteRef::Commit(first.clone());
+            vcs.fetch(&path, &commit).unwrap();
+            vcs.checkout(&path, &commit, None, false).unwrap();
+            assert_eq!(vcs.head(&path).unwrap(), first);
+            assert!(repository
+                .find_reference("refs/remotes/origin/other")
+                .is_ok());
+        }
+    }
+}
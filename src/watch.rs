@@ -0,0 +1,158 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::mpsc::{channel, RecvTimeoutError},
+    time::Duration,
+};
+
+use notify::{
+    event::{AccessKind, AccessMode},
+    Event, EventKind, RecursiveMode, Watcher,
+};
+
+/// Time without changes after which a batch of changes is synced, so saving
+/// many files at once syncs them together.
+const QUIET_PERIOD: Duration = Duration::from_millis(300);
+
+/// Watches a folder and calls `on_changes` with the paths changed in each
+/// batch of changes, relative to the folder. Changes inside `.git` are
+/// ignored. Only returns when watching fails.
+pub fn watch<F: FnMut(Vec<PathBuf>)>(folder: &Path, mut on_changes: F) -> Result<(), String> {
+    let folder = fs::canonicalize(folder).map_err(|e| e.to_string())?;
+    let (sender, receiver) = channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(|e| e.to_string())?;
+    watcher
+        .watch(&folder, RecursiveMode::Recursive)
+        .map_err(|e| e.to_string())?;
+
+    let mut changes: Vec<PathBuf> = Vec::new();
+    loop {
+        let event = if changes.is_empty() {
+            receiver.recv().map_err(|e| e.to_string())
+        } else {
+            match receiver.recv_timeout(QUIET_PERIOD) {
+                Ok(event) => Ok(event),
+                Err(RecvTimeoutError::Timeout) => {
+                    on_changes(std::mem::take(&mut changes));
+                    continue;
+                }
+                Err(e) => Err(e.to_string()),
+            }
+        }?;
+
+        let event = event.map_err(|e| e.to_string())?;
+        add_changes(&folder, event, &mut changes);
+    }
+}
+
+/// Adds the paths changed by an event to the batch of changes, relative to
+/// the folder and each only once. Changes inside `.git` are ignored.
+fn add_changes(folder: &Path, event: Event, changes: &mut Vec<PathBuf>) {
+    // Reading the files while syncing them reports accesses too.
+    if matches!(event.kind, EventKind::Access(x) if x != AccessKind::Close(AccessMode::Write)) {
+        return;
+    }
+    for path in event.paths {
+        let relative = match path.strip_prefix(folder) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => continue,
+        };
+        let in_git = relative
+            .components()
+            .any(|x| x == Component::Normal(".git".as_ref()));
+        if !relative.as_os_str().is_empty() && !in_git && !changes.contains(&relative) {
+            changes.push(relative);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, ModifyKind};
+
+    fn event(kind: EventKind, paths: &[&str]) -> Event {
+        paths.iter().fold(Event::new(kind), |event, path| {
+            event.add_path(PathBuf::from(path))
+        })
+    }
+
+    #[test]
+    fn changes_are_relative_and_batched_once() {
+        let folder = Path::new("/home/pi/web");
+        let mut changes = Vec::new();
+
+        add_changes(
+            folder,
+            event(
+                EventKind::Create(CreateKind::File),
+                &["/home/pi/web/index.html", "/home/pi/web/css/site.css"],
+            ),
+            &mut changes,
+        );
+        add_changes(
+            folder,
+            event(
+                EventKind::Modify(ModifyKind::Any),
+                &["/home/pi/web/index.html"],
+            ),
+            &mut changes,
+        );
+        add_changes(
+            folder,
+            event(
+                EventKind::Access(AccessKind::Close(AccessMode::Write)),
+                &["/home/pi/web/plugin.conf"],
+            ),
+            &mut changes,
+        );
+
+        assert_eq!(
+            changes,
+            [
+                PathBuf::from("index.html"),
+                PathBuf::from("css/site.css"),
+                PathBuf::from("plugin.conf")
+            ]
+        );
+    }
+
+    #[test]
+    fn git_reads_and_other_folders_are_ignored() {
+        let folder = Path::new("/home/pi/web");
+        let mut changes = Vec::new();
+
+        add_changes(
+            folder,
+            event(
+                EventKind::Modify(ModifyKind::Any),
+                &[
+                    "/home/pi/web",
+                    "/home/pi/web/.git/index",
+                    "/home/pi/web/lib/.git/HEAD",
+                    "/home/pi/website/index.html",
+                    "/home/pi/.gitignore",
+                ],
+            ),
+            &mut changes,
+        );
+        add_changes(
+            folder,
+            event(
+                EventKind::Access(AccessKind::Open(AccessMode::Read)),
+                &["/home/pi/web/index.html"],
+            ),
+            &mut changes,
+        );
+        add_changes(
+            folder,
+            event(
+                EventKind::Access(AccessKind::Close(AccessMode::Read)),
+                &["/home/pi/web/index.html"],
+            ),
+            &mut changes,
+        );
+
+        assert!(changes.is_empty());
+    }
+}